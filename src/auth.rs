@@ -0,0 +1,368 @@
+use std::{
+    collections::HashMap,
+    env::var,
+    fmt::Display,
+    io::Write,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use anyhow::{Result, anyhow, bail};
+use reqwest::StatusCode;
+use scraper::Html;
+use serde::{Deserialize, Deserializer, de};
+
+use crate::selector;
+
+const LOGIN_URL: &str = "https://archiveofourown.org/users/login";
+
+/// Why AO3 refused a login. AO3 answers bad logins with a 200 and a flash
+/// message, so these are recovered from the page rather than the status.
+#[derive(Debug, thiserror::Error)]
+pub enum LoginError {
+    #[error("AO3 didn't recognize that username/email and password combination")]
+    BadCredentials,
+    #[error("AO3 has locked this account: {0}")]
+    AccountLocked(String),
+    #[error(
+        "AO3 rejected the login form's CSRF token, the session probably expired mid-login; try again"
+    )]
+    Csrf,
+    #[error("AO3 refused the login: {0}")]
+    Rejected(String),
+    #[error("Login didn't go through, AO3 still shows the page as logged out")]
+    NotLoggedIn,
+}
+
+/// Service name passwords are saved under in the OS keyring.
+const KEYRING_SERVICE: &str = "ao3_wrapped";
+
+/// Where to get an account's password from when logging in.
+#[derive(Debug, Clone)]
+pub enum PasswordSource {
+    /// Ask every time
+    Prompt,
+    /// Read it from the named environment variable
+    Env(String),
+    /// The OS keyring (Secret Service on Linux), filled in by `store-password`
+    Keyring,
+    /// The first line printed by a shell command, like `pass show ao3`
+    Command(String),
+}
+
+impl PasswordSource {
+    fn read(&self, username: &str) -> Result<String> {
+        match self {
+            Self::Prompt => Ok(prompt("Enter your password: ", true)),
+            Self::Env(name) => var(name).map_err(|_| anyhow!("{name} isn't set")),
+            Self::Keyring => keyring::Entry::new(KEYRING_SERVICE, username)?
+                .get_password()
+                .map_err(|e| anyhow!("Couldn't read {username}'s password from the keyring: {e}")),
+            Self::Command(command) => run_password_command(command),
+        }
+    }
+}
+
+impl Display for PasswordSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Prompt => write!(f, "prompt"),
+            Self::Env(name) => write!(f, "env:{name}"),
+            Self::Keyring => write!(f, "keyring"),
+            Self::Command(command) => write!(f, "command:{command}"),
+        }
+    }
+}
+
+/// Parses the same forms `Display` writes, for passing a source on the
+/// command line or as a string in the config.
+impl FromStr for PasswordSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "prompt" => Ok(Self::Prompt),
+            _ if s == "keyring" => Ok(Self::Keyring),
+            Some(("env", name)) if !name.is_empty() => Ok(Self::Env(name.to_string())),
+            Some(("command", command)) if !command.is_empty() => {
+                Ok(Self::Command(command.to_string()))
+            }
+            _ => Err(format!(
+                "expected prompt, keyring, env:<VARIABLE> or command:<COMMAND>, got {s:?}"
+            )),
+        }
+    }
+}
+
+/// Accepts the string forms `FromStr` parses as well as tables like
+/// `{ env = "AO3_PASSWORD" }` and `{ command = "pass show ao3" }`.
+impl<'de> Deserialize<'de> for PasswordSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "snake_case")]
+        enum Table {
+            Env(String),
+            Command(String),
+        }
+
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = PasswordSource;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(
+                    f,
+                    "\"prompt\", \"keyring\", \"env:<VARIABLE>\", \"command:<COMMAND>\", \
+                     {{ env = \"<VARIABLE>\" }} or {{ command = \"<COMMAND>\" }}"
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+                s.parse().map_err(E::custom)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Ok(
+                    match Table::deserialize(de::value::MapAccessDeserializer::new(map))? {
+                        Table::Env(name) => PasswordSource::Env(name),
+                        Table::Command(command) => PasswordSource::Command(command),
+                    },
+                )
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Runs `command` through the shell and returns the first line it prints.
+/// Its stdin and stderr stay attached to the terminal so it can ask for a
+/// passphrase, as `pass` does.
+fn run_password_command(command: &str) -> Result<String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| anyhow!("Couldn't run password command: {e}"))?;
+    if !output.status.success() {
+        bail!("Password command failed with {}", output.status);
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| anyhow!("Password command printed something that isn't UTF-8"))?;
+    match stdout.lines().next() {
+        Some(password) if !password.is_empty() => Ok(password.to_string()),
+        _ => bail!("Password command didn't print a password"),
+    }
+}
+
+/// Saves `username`'s password in the OS keyring for `PasswordSource::Keyring`.
+pub fn store_password(username: &str) -> Result<()> {
+    let password = prompt(&format!("Enter the password for {username}: "), true);
+    keyring::Entry::new(KEYRING_SERVICE, username)?
+        .set_password(&password)
+        .map_err(|e| anyhow!("Couldn't save {username}'s password to the keyring: {e}"))?;
+    Ok(())
+}
+
+pub async fn get_csrf(client: &reqwest::Client) -> Result<String> {
+    let res = client
+        .get(LOGIN_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let doc = Html::parse_document(&res);
+    Ok(doc
+        .select(&selector("meta[name=\"csrf-token\"]"))
+        .next()
+        .and_then(|meta| meta.attr("content"))
+        .ok_or(LoginError::Csrf)?
+        .to_string())
+}
+
+/// Logs in as `login`, or whoever `AO3_USERNAME` or the prompt names if
+/// that's `None`, and returns the username AO3 shows for the new session,
+/// which may differ from what was typed if the user logged in with their
+/// email. Without a `password` source, `AO3_PASSWORD` is used if it's set.
+pub async fn sign_in(
+    client: &reqwest::Client,
+    csrf: &str,
+    login: Option<&str>,
+    password: Option<&PasswordSource>,
+) -> Result<String> {
+    let username = match login {
+        Some(login) => login.to_string(),
+        None => var("AO3_USERNAME").unwrap_or_else(|_| prompt("Enter your username: ", false)),
+    };
+    let password = match password {
+        Some(source) => source.read(&username)?,
+        None => var("AO3_PASSWORD").unwrap_or_else(|_| prompt("Enter your password: ", true)),
+    };
+
+    let params = [
+        ("utf8", "✓"),
+        ("authenticity_token", csrf),
+        ("user[login]", &username),
+        ("user[password]", &password),
+        ("user[remember_me]", "1"),
+        ("commit", "Log in"),
+    ]
+    .into_iter()
+    .collect::<HashMap<&str, &str>>();
+
+    let res = client
+        .post(LOGIN_URL)
+        .header("Referer", LOGIN_URL)
+        .header("Origin", "https://archiveofourown.org")
+        .form(&params)
+        .send()
+        .await?;
+
+    if res.status() == StatusCode::UNPROCESSABLE_ENTITY {
+        return Err(LoginError::Csrf.into());
+    }
+    let doc = Html::parse_document(&res.error_for_status()?.text().await?);
+
+    Ok(verify_login(&doc)?)
+}
+
+/// Checks the page AO3 lands on after logging in for either the logged-in
+/// user's greeting or the flash explaining why the login failed.
+fn verify_login(doc: &Html) -> Result<String, LoginError> {
+    if let Some(username) = logged_in_user(doc) {
+        return Ok(username);
+    }
+
+    let Some(flash) = doc.select(&selector("div.flash.error")).next() else {
+        return Err(LoginError::NotLoggedIn);
+    };
+    let message = flash.text().collect::<String>().trim().to_string();
+    let lower = message.to_lowercase();
+
+    Err(if lower.contains("locked") {
+        LoginError::AccountLocked(message)
+    } else if lower.contains("session") && lower.contains("expired") {
+        LoginError::Csrf
+    } else if lower.contains("password") || lower.contains("doesn't match") {
+        LoginError::BadCredentials
+    } else {
+        LoginError::Rejected(message)
+    })
+}
+
+/// Returns who the client's cookies are logged in as, if anyone.
+pub async fn current_user(client: &reqwest::Client) -> Result<Option<String>> {
+    let res = client
+        .get("https://archiveofourown.org/")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(logged_in_user(&Html::parse_document(&res)))
+}
+
+/// Reads the username out of the "Hi, <user>!" greeting in the page header,
+/// which is only present for logged-in sessions.
+pub fn logged_in_user(doc: &Html) -> Option<String> {
+    let link = doc
+        .select(&selector("#greeting a[href^='/users/']"))
+        .next()?;
+    link.attr("href")?
+        .strip_prefix("/users/")?
+        .split('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+fn prompt(p: &str, secure: bool) -> String {
+    if secure {
+        loop {
+            match rpassword::prompt_password(p) {
+                Ok(pass) => return pass,
+                Err(_) => eprintln!("Invalid password"),
+            }
+        }
+    } else {
+        loop {
+            print!("{p}");
+            let _ = std::io::stdout().flush();
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).unwrap();
+            let line = line.trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            return line;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login_page(flash: &str) -> Html {
+        Html::parse_document(&format!(
+            r#"<div id="header"><p class="user actions"><a id="login-dropdown" href="/users/login">Log In</a></p></div>
+            <div id="main"><div class="flash error">{flash}</div></div>"#
+        ))
+    }
+
+    #[test]
+    fn logged_in_page_gives_the_username() {
+        let doc = Html::parse_document(
+            r#"<ul id="greeting"><li class="dropdown"><a href="/users/some_reader/pseuds/me">Hi, me!</a></li></ul>
+            <div class="flash notice">Successfully logged in.</div>"#,
+        );
+        assert_eq!(verify_login(&doc).unwrap(), "some_reader");
+    }
+
+    #[test]
+    fn flashes_map_to_login_errors() {
+        let bad_password = login_page(
+            "The password or user name you entered doesn't match our records. Please try again \
+             or reset your password.",
+        );
+        assert!(matches!(
+            verify_login(&bad_password),
+            Err(LoginError::BadCredentials)
+        ));
+
+        let locked = login_page(
+            "Your account has been locked for 5 minutes due to too many failed login attempts.",
+        );
+        assert!(matches!(
+            verify_login(&locked),
+            Err(LoginError::AccountLocked(message)) if message.starts_with("Your account")
+        ));
+
+        let expired = login_page(
+            "Your current session has expired and we can't authenticate your request. Try \
+             logging in again, refreshing the page, or clearing your cache.",
+        );
+        assert!(matches!(verify_login(&expired), Err(LoginError::Csrf)));
+
+        let other = login_page("Sorry, something went wrong.");
+        assert!(matches!(
+            verify_login(&other),
+            Err(LoginError::Rejected(message)) if message == "Sorry, something went wrong."
+        ));
+    }
+
+    #[test]
+    fn no_greeting_or_flash_is_not_logged_in() {
+        let doc = Html::parse_document(r#"<ul id="greeting"></ul><div id="main"></div>"#);
+        assert!(matches!(verify_login(&doc), Err(LoginError::NotLoggedIn)));
+    }
+}
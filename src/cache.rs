@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+/// On-disk store of raw history pages, so parsing can be rerun without
+/// fetching anything from AO3 again.
+#[derive(Debug)]
+pub struct PageCache {
+    dir: PathBuf,
+}
+
+impl PageCache {
+    /// Pages are laid out as `<root>/<username>/<scrape_type>/page_<n>.html`.
+    pub fn new(root: impl AsRef<Path>, username: &str, scrape_type: &str) -> Self {
+        Self {
+            dir: root.as_ref().join(username).join(scrape_type),
+        }
+    }
+
+    fn page_path(&self, page: u32) -> PathBuf {
+        self.dir.join(format!("page_{page}.html"))
+    }
+
+    pub fn store(&self, page: u32, html: &str) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.page_path(page), html)?;
+        Ok(())
+    }
+
+    /// Every cached page number, in order. There can be gaps where pages
+    /// were skipped over on the way to the requested year.
+    pub fn pages(&self) -> Result<Vec<u32>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut pages = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            if let Some(page) = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("page_")?.strip_suffix(".html"))
+                .and_then(|n| n.parse().ok())
+            {
+                pages.push(page);
+            }
+        }
+        pages.sort_unstable();
+        Ok(pages)
+    }
+
+    pub fn contains(&self, page: u32) -> bool {
+        self.page_path(page).exists()
+    }
+
+    /// Returns the cached page, or `None` if it was never fetched.
+    pub fn load(&self, page: u32) -> Result<Option<String>> {
+        if !self.contains(page) {
+            return Ok(None);
+        }
+        Ok(Some(std::fs::read_to_string(self.page_path(page))?))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
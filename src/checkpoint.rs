@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{history::HistoryEntry, range::DateRange};
+
+/// Progress of an in-flight scrape, written after every completed page so an
+/// interrupted run can pick up where it left off.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub username: String,
+    pub scrape_type: String,
+    pub range: DateRange,
+    /// The last page that was fully processed
+    pub page: u32,
+    /// Everything in range parsed so far
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl Checkpoint {
+    fn path(dir: &Path, range: &DateRange) -> PathBuf {
+        dir.join(format!("checkpoint_{}.json", range.label()))
+    }
+
+    pub fn exists(dir: &Path, range: &DateRange) -> bool {
+        Self::path(dir, range).exists()
+    }
+
+    /// Loads the checkpoint for `range` from `dir`.
+    pub fn load(dir: &Path, range: &DateRange) -> Result<Self> {
+        let checkpoint: Self =
+            serde_json::from_str(&std::fs::read_to_string(Self::path(dir, range))?)?;
+        if checkpoint.range != *range {
+            bail!(
+                "Checkpoint is for {}, not {range}; delete {} to start over",
+                checkpoint.range,
+                Self::path(dir, range).display()
+            );
+        }
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint, replacing any previous one. It's written to a
+    /// temporary path first so a crash mid-write never leaves a truncated
+    /// checkpoint behind.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let path = Self::path(dir, &self.range);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string(self)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint for `range` once a scrape has finished.
+    pub fn clear(dir: &Path, range: &DateRange) -> Result<()> {
+        let path = Self::path(dir, range);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow, bail};
+use serde::Deserialize;
+
+use crate::auth::PasswordSource;
+
+/// Named profiles for running the tool against several accounts, read from
+/// a TOML file like:
+///
+/// ```toml
+/// default_profile = "me"
+///
+/// [profiles.me]
+/// username = "my_ao3_name"
+/// password = "prompt"
+/// data_dir = "~/ao3/me"
+/// delay_ms = 8000
+///
+/// [profiles.partner]
+/// username = "their_ao3_name"
+/// password = { env = "PARTNER_AO3_PASSWORD" }
+///
+/// [profiles.work]
+/// username = "work_ao3_name"
+/// password = { command = "pass show ao3/work" }
+/// ```
+///
+/// `password = "keyring"` reads it from the OS keyring instead, after
+/// saving it there with `store-password`. The `--password` forms, like
+/// `password = "env:PARTNER_AO3_PASSWORD"`, work here too.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Used when `--profile` isn't given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Where the config was read from, if anywhere
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Defaults for one account. Anything given on the command line wins.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub username: Option<String>,
+    /// Where the password comes from, `AO3_PASSWORD` or a prompt if unset
+    pub password: Option<PasswordSource>,
+    pub data_dir: Option<PathBuf>,
+    pub delay_ms: Option<u64>,
+}
+
+impl Config {
+    /// `~/.config/ao3_wrapped/config.toml` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("ao3_wrapped").join("config.toml"))
+    }
+
+    /// Reads the config at `path`, or at the default path if it's `None`.
+    /// Only an explicitly given config has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Couldn't read config {}: {e}", path.display()))?;
+        let mut config: Self = toml::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid config {}: {e}", path.display()))?;
+        for profile in config.profiles.values_mut() {
+            profile.data_dir = profile.data_dir.take().map(expand_home);
+        }
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// The profile called `name`, falling back to the default profile and
+    /// then to an empty one that leaves everything to the command line.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(Profile::default());
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone()),
+            None => bail!(
+                "No profile named {name:?} in {}",
+                self.path
+                    .as_deref()
+                    .map_or("the config".into(), |p| p.display().to_string())
+            ),
+        }
+    }
+}
+
+/// Expands a leading `~` to the home directory, since config files don't go
+/// through a shell.
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    }
+}
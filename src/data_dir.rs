@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow, bail};
+
+/// Where everything the tool keeps between runs lives. Each account gets its
+/// own subdirectory so several accounts can share one data directory:
+///
+/// ```text
+/// <root>/session.json           session when no account was named
+/// <root>/cache/<user>/...       raw history pages
+/// <root>/<user>/session.json
+/// <root>/<user>/login           email the account logs in with, if any
+/// <root>/<user>/works_2024.csv
+/// <root>/<user>/checkpoint_2024.json
+/// ```
+#[derive(Debug)]
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    /// Uses `root`, or the platform's data directory (`~/.local/share/ao3_wrapped`
+    /// on Linux) if it's `None`.
+    pub fn new(root: Option<PathBuf>) -> Result<Self> {
+        let root = match root {
+            Some(root) => root,
+            None => dirs::data_dir()
+                .ok_or_else(|| anyhow!("Couldn't find a data directory, pass --data-dir"))?
+                .join("ao3_wrapped"),
+        };
+        Ok(Self { root })
+    }
+
+    pub fn user(&self, username: &str) -> PathBuf {
+        self.root.join(username)
+    }
+
+    pub fn cache(&self) -> PathBuf {
+        self.root.join("cache")
+    }
+
+    /// The saved login for `login`, or the shared one if no account was
+    /// named. Returns `None` for an email no account has logged in with yet.
+    pub fn session(&self, login: Option<&str>) -> Option<PathBuf> {
+        match login {
+            Some(login) => Some(self.user_session(&self.username_for(login)?)),
+            None => Some(self.root.join("session.json")),
+        }
+    }
+
+    pub fn user_session(&self, username: &str) -> PathBuf {
+        self.user(username).join("session.json")
+    }
+
+    /// The account `login` names. Usernames are taken as they are, while an
+    /// email is looked up among the accounts that have logged in with it,
+    /// since everything is kept under the AO3 username.
+    pub fn username_for(&self, login: &str) -> Option<String> {
+        if !login.contains('@') {
+            return Some(login.to_string());
+        }
+        std::fs::read_dir(&self.root)
+            .ok()?
+            .flatten()
+            .find_map(|entry| {
+                let saved = std::fs::read_to_string(entry.path().join("login")).ok()?;
+                saved
+                    .trim()
+                    .eq_ignore_ascii_case(login)
+                    .then(|| entry.file_name().to_string_lossy().into_owned())
+            })
+    }
+
+    /// Records that `username` logged in as `login`, so later runs given an
+    /// email can find the account.
+    pub fn remember_login(&self, username: &str, login: &str) -> Result<()> {
+        if login.contains('@') {
+            let dir = self.user(username);
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join("login"), login)?;
+        }
+        Ok(())
+    }
+
+    /// Picks the account to read data for when none was named, which only
+    /// works if there's just the one.
+    pub fn only_user(&self) -> Result<String> {
+        let mut users = Vec::new();
+        if self.root.exists() {
+            for entry in std::fs::read_dir(&self.root)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type()?.is_dir() && name != "cache" {
+                    users.push(name);
+                }
+            }
+        }
+        match &users[..] {
+            [user] => Ok(user.clone()),
+            [] => bail!("No scraped data found in {}", self.root.display()),
+            _ => bail!(
+                "Found data for several accounts in {} ({}), pick one with --user",
+                self.root.display(),
+                users.join(", ")
+            ),
+        }
+    }
+}
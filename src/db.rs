@@ -0,0 +1,268 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rusqlite::{Connection, Transaction, params};
+
+use crate::{history::HistoryEntry, range::DateRange};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scrape_runs (
+    id INTEGER PRIMARY KEY,
+    username TEXT NOT NULL,
+    scrape_type TEXT NOT NULL,
+    range TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    entries INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS works (
+    work_id INTEGER PRIMARY KEY,
+    work_url TEXT,
+    title TEXT NOT NULL,
+    last_updated TEXT NOT NULL,
+    rating TEXT NOT NULL,
+    status TEXT NOT NULL,
+    word_count INTEGER NOT NULL,
+    kudos INTEGER NOT NULL,
+    hits INTEGER NOT NULL,
+    language TEXT,
+    current_chapters INTEGER,
+    total_chapters INTEGER,
+    comments INTEGER NOT NULL,
+    bookmarks INTEGER NOT NULL,
+    published TEXT,
+    summary TEXT,
+    summary_html TEXT,
+    series_id INTEGER,
+    series_name TEXT,
+    series_part INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS history (
+    username TEXT NOT NULL,
+    work_id INTEGER NOT NULL REFERENCES works (work_id),
+    last_visited TEXT NOT NULL,
+    visitations INTEGER NOT NULL,
+    run_id INTEGER NOT NULL REFERENCES scrape_runs (id),
+    PRIMARY KEY (username, work_id, last_visited)
+);
+
+CREATE TABLE IF NOT EXISTS authors (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    url TEXT NOT NULL DEFAULT '',
+    UNIQUE (name, url)
+);
+
+CREATE TABLE IF NOT EXISTS work_authors (
+    work_id INTEGER NOT NULL REFERENCES works (work_id),
+    author_id INTEGER NOT NULL REFERENCES authors (id),
+    PRIMARY KEY (work_id, author_id)
+);
+
+CREATE TABLE IF NOT EXISTS fandoms (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS work_fandoms (
+    work_id INTEGER NOT NULL REFERENCES works (work_id),
+    fandom_id INTEGER NOT NULL REFERENCES fandoms (id),
+    PRIMARY KEY (work_id, fandom_id)
+);
+
+-- Warnings, ship types, relationships, characters and additional tags,
+-- told apart by `kind`
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (kind, name)
+);
+
+CREATE TABLE IF NOT EXISTS work_tags (
+    work_id INTEGER NOT NULL REFERENCES works (work_id),
+    tag_id INTEGER NOT NULL REFERENCES tags (id),
+    PRIMARY KEY (work_id, tag_id)
+);
+";
+
+/// A SQLite reading log that every scrape can be added to, keeping one row
+/// per work and per visit across runs and years.
+#[derive(Debug)]
+pub struct HistoryDb {
+    conn: Connection,
+}
+
+impl HistoryDb {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Records a finished scrape and upserts its entries. Works keep their
+    /// most recent metadata and tags, and a visit already in the log only has
+    /// its visit count updated. Entries without a work ID can't be keyed and
+    /// are left out. Returns how many entries were saved.
+    pub fn record(
+        &mut self,
+        username: &str,
+        scrape_type: &str,
+        range: &DateRange,
+        entries: &[HistoryEntry],
+    ) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+
+        let keyed: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| Some((entry.work_id?, entry)))
+            .collect();
+
+        tx.execute(
+            "INSERT INTO scrape_runs (username, scrape_type, range, finished_at, entries)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                username,
+                scrape_type,
+                range.label(),
+                chrono::Utc::now(),
+                keyed.len()
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+
+        for &(work_id, entry) in &keyed {
+            upsert_work(&tx, work_id, entry)?;
+            tx.execute(
+                "INSERT INTO history (username, work_id, last_visited, visitations, run_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (username, work_id, last_visited) DO UPDATE SET
+                     visitations = excluded.visitations,
+                     run_id = excluded.run_id",
+                params![
+                    username,
+                    work_id,
+                    entry.last_visited,
+                    entry.visitations,
+                    run_id
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(keyed.len())
+    }
+}
+
+fn upsert_work(tx: &Transaction, work_id: u64, entry: &HistoryEntry) -> Result<()> {
+    tx.execute(
+        "INSERT INTO works (
+             work_id, work_url, title, last_updated, rating, status, word_count, kudos,
+             hits, language, current_chapters, total_chapters, comments, bookmarks,
+             published, summary, summary_html, series_id, series_name, series_part
+         )
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                 ?18, ?19, ?20)
+         ON CONFLICT (work_id) DO UPDATE SET
+             work_url = excluded.work_url,
+             title = excluded.title,
+             last_updated = excluded.last_updated,
+             rating = excluded.rating,
+             status = excluded.status,
+             word_count = excluded.word_count,
+             kudos = excluded.kudos,
+             hits = excluded.hits,
+             language = excluded.language,
+             current_chapters = excluded.current_chapters,
+             total_chapters = excluded.total_chapters,
+             comments = excluded.comments,
+             bookmarks = excluded.bookmarks,
+             published = excluded.published,
+             summary = excluded.summary,
+             summary_html = coalesce(excluded.summary_html, works.summary_html),
+             series_id = excluded.series_id,
+             series_name = excluded.series_name,
+             series_part = excluded.series_part",
+        params![
+            work_id,
+            entry.work_url,
+            entry.title,
+            entry.last_updated,
+            entry.rating,
+            entry.status,
+            entry.word_count,
+            entry.kudos,
+            entry.hits,
+            entry.language,
+            entry.current_chapters,
+            entry.total_chapters,
+            entry.comments,
+            entry.bookmarks,
+            entry.published,
+            entry.summary,
+            entry.summary_html,
+            entry.series_id,
+            entry.series_name,
+            entry.series_part,
+        ],
+    )?;
+
+    // Tags get edited, so replace rather than add to the old links
+    for table in ["work_authors", "work_fandoms", "work_tags"] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE work_id = ?1"),
+            [work_id],
+        )?;
+    }
+
+    for (i, name) in entry.authors.iter().enumerate() {
+        let url = entry.author_urls.get(i).map_or("", String::as_str);
+        tx.execute(
+            "INSERT INTO authors (name, url) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+            params![name, url],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO work_authors (work_id, author_id)
+             SELECT ?1, id FROM authors WHERE name = ?2 AND url = ?3",
+            params![work_id, name, url],
+        )?;
+    }
+
+    for name in &entry.fandoms {
+        tx.execute(
+            "INSERT INTO fandoms (name) VALUES (?1) ON CONFLICT DO NOTHING",
+            [name],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO work_fandoms (work_id, fandom_id)
+             SELECT ?1, id FROM fandoms WHERE name = ?2",
+            params![work_id, name],
+        )?;
+    }
+
+    let tags = [
+        ("warning", &entry.warnings),
+        ("ship_type", &entry.ship_types),
+        ("relationship", &entry.ships),
+        ("character", &entry.characters),
+        ("freeform", &entry.additional_tags),
+    ];
+    for (kind, names) in tags {
+        for name in names {
+            tx.execute(
+                "INSERT INTO tags (kind, name) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+                params![kind, name],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO work_tags (work_id, tag_id)
+                 SELECT ?1, id FROM tags WHERE kind = ?2 AND name = ?3",
+                params![work_id, kind, name],
+            )?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use reqwest::{StatusCode, header::RETRY_AFTER};
+use tokio::time::sleep;
+
+/// How hard to try fetching a page before giving up on the run.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts per page, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failure
+    pub base_delay: Duration,
+    /// Upper bound on any single backoff. A server's `Retry-After` is always
+    /// waited out in full, since retrying sooner only earns another 429.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter, so concurrent runs that hit the
+    /// rate limit together don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        ceiling.mul_f64(rand::random_range(0.5..=1.0))
+    }
+}
+
+/// Fetches `url` as text, retrying transient failures according to `policy`.
+///
+/// Rate limiting (429), server errors and transport errors are retried.
+/// Anything else, like a missing page or a permissions error, fails right
+/// away since asking again won't change the answer.
+pub async fn fetch_page(
+    client: &reqwest::Client,
+    url: &str,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let mut attempt = 1;
+    loop {
+        let (err, retry_after) = match client.get(url).send().await {
+            Ok(res) if res.status().is_success() => match res.text().await {
+                Ok(text) => return Ok(text),
+                Err(e) => (e.to_string(), None),
+            },
+            Ok(res) => {
+                let status = res.status();
+                if !is_retryable(status) {
+                    bail!("Fetching {url} failed with {status}, not retrying");
+                }
+                (status.to_string(), retry_after(&res))
+            }
+            Err(e) => (e.to_string(), None),
+        };
+
+        if attempt >= policy.max_attempts {
+            bail!("Fetching {url} failed after {attempt} attempts: {err}");
+        }
+
+        let delay = retry_after.unwrap_or_else(|| policy.backoff(attempt));
+        eprintln!(
+            "Attempt {attempt}/{} failed: {err}, retrying in {} ms...",
+            policy.max_attempts,
+            delay.as_millis()
+        );
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+/// Reads `Retry-After` on 429/503 responses, in either its delay-seconds or
+/// HTTP-date form.
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    if !matches!(
+        res.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.to_utc() - chrono::Utc::now()).to_std().ok()
+}
@@ -0,0 +1,570 @@
+use chrono::NaiveDate;
+use polars::prelude::*;
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::selector;
+
+/// One work from a user's history page, as of their last visit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub work_id: Option<u64>,
+    pub work_url: Option<String>,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub author_urls: Vec<String>,
+    pub last_updated: String,
+    pub fandoms: Vec<String>,
+    pub characters: Vec<String>,
+    pub ship_types: Vec<String>,
+    pub rating: String,
+    pub warnings: Vec<String>,
+    /// "Complete Work" or "Work in Progress"
+    pub status: String,
+    pub ships: Vec<String>,
+    pub additional_tags: Vec<String>,
+    pub word_count: u64,
+    pub kudos: u32,
+    pub hits: u32,
+    pub language: Option<String>,
+    pub current_chapters: Option<u32>,
+    /// `None` while the author hasn't decided on a total
+    pub total_chapters: Option<u32>,
+    pub comments: u32,
+    pub bookmarks: u32,
+    pub published: Option<NaiveDate>,
+    pub summary: Option<String>,
+    pub summary_html: Option<String>,
+    pub series_id: Option<u64>,
+    pub series_name: Option<String>,
+    pub series_part: Option<u32>,
+    pub last_visited: NaiveDate,
+    pub visitations: u32,
+}
+
+/// Everything parsed from one history page.
+#[derive(Debug, Default)]
+pub struct HistoryPage {
+    pub entries: Vec<HistoryEntry>,
+    /// Works listed on the page, including ones that couldn't be parsed
+    pub works: usize,
+    pub oldest_visit: Option<NaiveDate>,
+    /// Why each listed work that was left out of `entries` was left out
+    pub warnings: Vec<String>,
+}
+
+impl HistoryPage {
+    /// Whether nothing after this page can have been visited on or after
+    /// `date`, either because it reaches back past it or history ran out.
+    pub fn reaches_before(&self, date: NaiveDate) -> bool {
+        self.works == 0 || self.oldest_visit.is_some_and(|oldest| oldest < date)
+    }
+}
+
+/// Parses every work on a history page. Works that are missing required
+/// parts, like deleted works, are counted but left out with a warning.
+pub fn parse_history_page(html: &Html, keep_summary_html: bool) -> HistoryPage {
+    let work_list_sel =
+        Selector::parse("ol.reading.work.index.group li[class*='reading work blurb group']")
+            .unwrap();
+    let user_module_sel = Selector::parse("div.user.module.group h4").unwrap();
+    let header_sel = Selector::parse("div.header.module").unwrap();
+    let title_sel = Selector::parse("h4.heading a").unwrap();
+    let author_sel = Selector::parse("h4.heading a[rel='author']").unwrap();
+    let date_sel = Selector::parse("p").unwrap();
+    let fandom_sel = Selector::parse("h5.fandoms.heading a").unwrap();
+    let req_tag_sel = Selector::parse("ul li a span.text").unwrap();
+    let ship_sel = Selector::parse("ul.tags.commas li.relationships").unwrap();
+    let char_sel = Selector::parse("ul.tags.commas li.characters").unwrap();
+    let tag_sel = Selector::parse("ul.tags.commas li.freeforms").unwrap();
+    let stats_sel = Selector::parse("dl.stats").unwrap();
+    let words_sel = Selector::parse("dd.words").unwrap();
+    let kudos_sel = Selector::parse("dd.kudos a").unwrap();
+    let hits_sel = Selector::parse("dd.hits").unwrap();
+    let language_sel = Selector::parse("dd.language").unwrap();
+    let chapters_sel = Selector::parse("dd.chapters").unwrap();
+    let comments_sel = Selector::parse("dd.comments").unwrap();
+    let bookmarks_sel = Selector::parse("dd.bookmarks").unwrap();
+    let published_sel = Selector::parse("dd.published").unwrap();
+    let work_summary_sel = Selector::parse("blockquote.userstuff.summary").unwrap();
+    let series_sel = Selector::parse("ul.series li").unwrap();
+    let series_part_sel = Selector::parse("strong").unwrap();
+    let series_link_sel = Selector::parse("a[href^='/series/']").unwrap();
+
+    let mut page = HistoryPage::default();
+
+    for work in html.select(&work_list_sel) {
+        page.works += 1;
+        // What to call the work in warnings, since it may not have a title
+        let name = match work.select(&title_sel).next() {
+            Some(title) => format!("{:?}", title.text().collect::<String>()),
+            None => format!("work {} on the page", page.works),
+        };
+
+        // Get last visited date
+        let Some(user_module) = work.select(&user_module_sel).next() else {
+            page.warnings.push(format!(
+                "couldn't find when {name} was last visited, skipping it"
+            ));
+            continue;
+        };
+        let last_visited_text = user_module.text().collect::<String>();
+        let last_visited = last_visited(&last_visited_text);
+
+        let Some(last_visited) = parse_visit_date(last_visited) else {
+            page.warnings.push(format!(
+                "couldn't parse last visited date {last_visited:?} of {name}, skipping it"
+            ));
+            continue;
+        };
+        page.oldest_visit = Some(
+            page.oldest_visit
+                .map_or(last_visited, |oldest| oldest.min(last_visited)),
+        );
+
+        // Get title
+        let Some(header) = work.select(&header_sel).next() else {
+            page.warnings
+                .push(format!("couldn't find the header of {name}, skipping it"));
+            continue;
+        };
+        let Some(title_elem) = header.select(&title_sel).next() else {
+            page.warnings
+                .push(format!("couldn't find the title of {name}, skipping it"));
+            continue;
+        };
+        let title = title_elem.text().collect::<String>();
+
+        // Get work ID, which unlike the title is unique
+        let work_id: Option<u64> = title_elem
+            .attr("href")
+            .and_then(|href| href.strip_prefix("/works/"))
+            .and_then(|id| id.split(['/', '?']).next())
+            .and_then(|id| id.parse().ok());
+        let work_url = work_id.map(|id| format!("https://archiveofourown.org/works/{id}"));
+
+        // Get authors
+        let mut authors = Vec::new();
+        let mut author_urls = Vec::new();
+        for author in header.select(&author_sel) {
+            let author_text = author.text().collect::<String>();
+            if author_text != "orphan_account" {
+                if let Some(href) = author.attr("href") {
+                    author_urls.push(format!("https://archiveofourown.org{href}"));
+                }
+                authors.push(author_text);
+            }
+        }
+
+        // Get date last updated
+        let last_updated = header
+            .select(&date_sel)
+            .next()
+            .map(|e| e.text().collect::<String>())
+            .unwrap_or_default();
+
+        // Get fandoms
+        let fandoms = header
+            .select(&fandom_sel)
+            .map(|f| f.text().collect::<String>())
+            .collect();
+
+        // Get required tags (rating, warnings, ship types, status)
+        let req_tags: Vec<String> = header
+            .select(&req_tag_sel)
+            .map(|t| t.text().collect::<String>())
+            .collect();
+
+        let [rating, warnings, ship_types, status, ..] = &req_tags[..] else {
+            page.warnings.push(format!(
+                "{name} is missing some of its required tags, skipping it"
+            ));
+            continue;
+        };
+        let warnings = warnings.split(", ").map(str::to_string).collect();
+        let ship_types = ship_types.split(", ").map(str::to_string).collect();
+
+        // Get relationships, characters and additional tags
+        let ships = work
+            .select(&ship_sel)
+            .map(|s| s.text().collect::<String>())
+            .collect();
+        let characters = work
+            .select(&char_sel)
+            .map(|c| c.text().collect::<String>())
+            .collect();
+        let additional_tags = work
+            .select(&tag_sel)
+            .map(|t| t.text().collect::<String>())
+            .collect();
+
+        // Get summary
+        let work_summary_elem = work.select(&work_summary_sel).next();
+        let summary = work_summary_elem.map(plain_text);
+        let summary_html = work_summary_elem
+            .filter(|_| keep_summary_html)
+            .map(|e| e.inner_html().trim().to_string());
+
+        // Get series, "Part 3 of <series>". Works can be in several series, but
+        // only the first is kept.
+        let series = work.select(&series_sel).next();
+        let series_link = series.and_then(|s| s.select(&series_link_sel).next());
+        let series_id =
+            series_link.and_then(|a| a.attr("href")?.strip_prefix("/series/")?.parse().ok());
+        let series_name = series_link.map(|a| a.text().collect::<String>());
+        let series_part = series
+            .and_then(|s| s.select(&series_part_sel).next())
+            .and_then(|e| e.text().collect::<String>().replace(",", "").parse().ok());
+
+        // Get stats
+        let Some(stats_elem) = work.select(&stats_sel).next() else {
+            page.warnings
+                .push(format!("couldn't find the stats of {name}, skipping it"));
+            continue;
+        };
+        let number = |sel: &Selector| {
+            stats_elem.select(sel).next().and_then(|e| {
+                e.text()
+                    .collect::<String>()
+                    .replace(",", "")
+                    .parse::<u64>()
+                    .ok()
+            })
+        };
+
+        let word_count = number(&words_sel).unwrap_or(0);
+        let kudos = number(&kudos_sel).unwrap_or(0) as u32;
+        let hits = number(&hits_sel).unwrap_or(0) as u32;
+        // AO3 leaves these out entirely when there are none
+        let comments = number(&comments_sel).unwrap_or(0) as u32;
+        let bookmarks = number(&bookmarks_sel).unwrap_or(0) as u32;
+
+        let language = stats_elem
+            .select(&language_sel)
+            .next()
+            .map(|e| e.text().collect::<String>().trim().to_string());
+
+        // Chapters read "12/30", or "12/?" while the total isn't known yet
+        let chapters = stats_elem
+            .select(&chapters_sel)
+            .next()
+            .map(|e| e.text().collect::<String>())
+            .unwrap_or_default();
+        let (current_chapters, total_chapters) = match chapters.trim().split_once('/') {
+            Some((current, total)) => (
+                current.replace(",", "").parse().ok(),
+                total.replace(",", "").parse().ok(),
+            ),
+            None => (None, None),
+        };
+
+        let published = stats_elem.select(&published_sel).next().and_then(|e| {
+            NaiveDate::parse_from_str(e.text().collect::<String>().trim(), "%Y-%m-%d").ok()
+        });
+
+        // Get visitations
+        let visitations_text = last_visited_text
+            .split("Visited ")
+            .nth(1)
+            .and_then(|s| s.split_whitespace().next())
+            .unwrap_or("once");
+
+        let visitations = if visitations_text == "once" {
+            1
+        } else {
+            visitations_text.parse().unwrap_or(1)
+        };
+
+        page.entries.push(HistoryEntry {
+            work_id,
+            work_url,
+            title,
+            authors,
+            author_urls,
+            last_updated,
+            fandoms,
+            characters,
+            ship_types,
+            rating: rating.clone(),
+            warnings,
+            status: status.clone(),
+            ships,
+            additional_tags,
+            word_count,
+            kudos,
+            hits,
+            language,
+            current_chapters,
+            total_chapters,
+            comments,
+            bookmarks,
+            published,
+            summary,
+            summary_html,
+            series_id,
+            series_name,
+            series_part,
+            last_visited,
+            visitations,
+        });
+    }
+
+    page
+}
+
+/// Lays entries out as the works table, with multi-valued fields as lists.
+pub fn to_dataframe(entries: &[HistoryEntry]) -> PolarsResult<DataFrame> {
+    fn column<T>(entries: &[HistoryEntry], f: impl Fn(&HistoryEntry) -> T) -> Vec<T> {
+        entries.iter().map(f).collect()
+    }
+
+    fn list(
+        name: &str,
+        entries: &[HistoryEntry],
+        f: impl Fn(&HistoryEntry) -> &[String],
+    ) -> Column {
+        let mut builder =
+            ListStringChunkedBuilder::new(name.into(), entries.len(), entries.len() * 4);
+        for entry in entries {
+            builder.append_values_iter(f(entry).iter().map(String::as_str));
+        }
+        builder.finish().into_column()
+    }
+
+    DataFrame::new(vec![
+        Column::new("work_id".into(), column(entries, |e| e.work_id)),
+        Column::new("work_url".into(), column(entries, |e| e.work_url.clone())),
+        Column::new("title".into(), column(entries, |e| e.title.clone())),
+        list("authors", entries, |e| &e.authors),
+        list("author_urls", entries, |e| &e.author_urls),
+        Column::new(
+            "last_updated".into(),
+            column(entries, |e| e.last_updated.clone()),
+        ),
+        list("fandoms", entries, |e| &e.fandoms),
+        list("characters", entries, |e| &e.characters),
+        list("ship_types", entries, |e| &e.ship_types),
+        Column::new("rating".into(), column(entries, |e| e.rating.clone())),
+        list("warnings", entries, |e| &e.warnings),
+        Column::new("work_stats".into(), column(entries, |e| e.status.clone())),
+        list("ships", entries, |e| &e.ships),
+        list("additional_tags", entries, |e| &e.additional_tags),
+        Column::new("word_count".into(), column(entries, |e| e.word_count)),
+        Column::new("kudos".into(), column(entries, |e| e.kudos)),
+        Column::new("hits".into(), column(entries, |e| e.hits)),
+        Column::new("language".into(), column(entries, |e| e.language.clone())),
+        Column::new(
+            "current_chapters".into(),
+            column(entries, |e| e.current_chapters),
+        ),
+        Column::new(
+            "total_chapters".into(),
+            column(entries, |e| e.total_chapters),
+        ),
+        Column::new("comments".into(), column(entries, |e| e.comments)),
+        Column::new("bookmarks".into(), column(entries, |e| e.bookmarks)),
+        Column::new("published".into(), column(entries, |e| e.published)),
+        Column::new("summary".into(), column(entries, |e| e.summary.clone())),
+        Column::new(
+            "summary_html".into(),
+            column(entries, |e| e.summary_html.clone()),
+        ),
+        Column::new("series_id".into(), column(entries, |e| e.series_id)),
+        Column::new(
+            "series_name".into(),
+            column(entries, |e| e.series_name.clone()),
+        ),
+        Column::new("series_part".into(), column(entries, |e| e.series_part)),
+        Column::new(
+            "user_last_visited".into(),
+            column(entries, |e| e.last_visited),
+        ),
+        Column::new(
+            "user_visitations".into(),
+            column(entries, |e| e.visitations),
+        ),
+    ])
+}
+
+/// Every parseable last-visited date on a history page.
+pub fn visit_dates(html: &Html) -> Vec<NaiveDate> {
+    html.select(&selector("div.user.module.group h4"))
+        .filter_map(|h4| parse_visit_date(last_visited(&h4.text().collect::<String>())))
+        .collect()
+}
+
+/// Pulls the date out of a "Last visited: 12 Mar 2024 (Latest version.)" heading.
+fn last_visited(text: &str) -> &str {
+    text.trim()
+        .strip_prefix("Last visited:")
+        .unwrap_or("")
+        .lines()
+        .next()
+        .unwrap_or("")
+        .trim()
+}
+
+fn parse_visit_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%d %b %Y").ok()
+}
+
+/// Flattens user-written HTML to text, with a blank line between paragraphs
+/// and line breaks kept.
+fn plain_text(el: ElementRef) -> String {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    for node in el.descendants() {
+        match node.value() {
+            Node::Text(text) => current.push_str(text),
+            Node::Element(e) if e.name() == "br" => current.push('\n'),
+            Node::Element(e) if e.name() == "p" && !current.trim().is_empty() => {
+                paragraphs.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => {}
+        }
+    }
+    if !current.trim().is_empty() {
+        paragraphs.push(current.trim().to_string());
+    }
+    paragraphs.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"
+<ol class="reading work index group">
+<li class="reading work blurb group">
+  <div class="header module">
+    <h4 class="heading"><a href="/works/1001">First</a> by <a rel="author" href="/users/a/pseuds/a">a</a>, <a rel="author" href="/users/orphan_account/pseuds/orphan_account">orphan_account</a></h4>
+    <h5 class="fandoms heading"><a class="tag">Fandom A</a>, <a class="tag">Fandom, B</a></h5>
+    <ul class="required-tags">
+      <li><a><span class="rating"><span class="text">Mature</span></span></a></li>
+      <li><a><span class="warnings"><span class="text">Major Character Death, Graphic Depictions Of Violence</span></span></a></li>
+      <li><a><span class="category"><span class="text">M/M, F/F</span></span></a></li>
+      <li><a><span class="iswip"><span class="text">Work in Progress</span></span></a></li>
+    </ul>
+    <p class="datetime">01 Feb 2025</p>
+  </div>
+  <ul class="tags commas">
+    <li class="relationships"><a class="tag">A/B</a></li>
+    <li class="characters"><a class="tag">A</a></li><li class="characters"><a class="tag">B</a></li>
+    <li class="freeforms"><a class="tag">Hurt/Comfort, but mostly hurt</a></li>
+  </ul>
+  <blockquote class="userstuff summary"><p>One<br>two</p><p>Three</p></blockquote>
+  <ul class="series"><li>Part <strong>3</strong> of <a href="/series/77">A Series</a></li></ul>
+  <dl class="stats">
+    <dd class="language">English</dd>
+    <dd class="words">12,345</dd>
+    <dd class="chapters"><a>12</a>/?</dd>
+    <dd class="kudos"><a>1,001</a></dd>
+    <dd class="hits">20,000</dd>
+    <dd class="published">2020-01-02</dd>
+  </dl>
+  <div class="user module group"><h4 class="viewed heading">
+    <span>Last visited:</span> 05 Mar 2025
+    (Update available.)
+    Visited 4 times
+  </h4></div>
+</li>
+<li class="reading work blurb group">
+  <div class="header module">
+    <h4 class="heading"><a href="/works/1002">Second</a></h4>
+    <ul class="required-tags">
+      <li><a><span class="text">General Audiences</span></a></li>
+      <li><a><span class="text">No Archive Warnings Apply</span></a></li>
+      <li><a><span class="text">Gen</span></a></li>
+      <li><a><span class="text">Complete Work</span></a></li>
+    </ul>
+  </div>
+  <dl class="stats"><dd class="words">100</dd><dd class="chapters">1/1</dd></dl>
+  <div class="user module group"><h4 class="viewed heading">
+    <span>Last visited:</span> 01 Jan 2025
+    (Latest version.)
+    Visited once
+  </h4></div>
+</li>
+<li class="reading work blurb group">
+  <div class="header module"><h4 class="heading"><a href="/works/1003">Third</a></h4></div>
+  <div class="user module group"><h4 class="viewed heading">
+    <span>Last visited:</span> sometime
+  </h4></div>
+</li>
+<li class="reading work blurb group">
+  <div class="header module"><h4 class="heading"><a href="/works/1004">Fourth</a></h4></div>
+  <div class="user module group"><h4 class="viewed heading">
+    <span>Last visited:</span> 01 Dec 2024
+    (Latest version.)
+    Visited once
+  </h4></div>
+</li>
+</ol>
+"#;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn parses_history_page() {
+        let page = parse_history_page(&Html::parse_document(PAGE), true);
+        assert_eq!(page.works, 4);
+        assert_eq!(page.entries.len(), 2);
+        assert_eq!(page.oldest_visit, Some(date(2024, 12, 1)));
+
+        let first = &page.entries[0];
+        assert_eq!(first.work_id, Some(1001));
+        assert_eq!(first.title, "First");
+        assert_eq!(first.authors, ["a"]);
+        assert_eq!(first.fandoms, ["Fandom A", "Fandom, B"]);
+        assert_eq!(first.rating, "Mature");
+        assert_eq!(
+            first.warnings,
+            ["Major Character Death", "Graphic Depictions Of Violence"]
+        );
+        assert_eq!(first.ship_types, ["M/M", "F/F"]);
+        assert_eq!(first.status, "Work in Progress");
+        assert_eq!(first.ships, ["A/B"]);
+        assert_eq!(first.characters, ["A", "B"]);
+        assert_eq!(first.additional_tags, ["Hurt/Comfort, but mostly hurt"]);
+        assert_eq!(first.word_count, 12345);
+        assert_eq!(first.kudos, 1001);
+        assert_eq!(first.comments, 0);
+        assert_eq!(first.language.as_deref(), Some("English"));
+        assert_eq!(first.current_chapters, Some(12));
+        assert_eq!(first.total_chapters, None);
+        assert_eq!(first.published, Some(date(2020, 1, 2)));
+        assert_eq!(first.summary.as_deref(), Some("One\ntwo\n\nThree"));
+        assert_eq!(
+            first.summary_html.as_deref(),
+            Some("<p>One<br>two</p><p>Three</p>")
+        );
+        assert_eq!(first.series_id, Some(77));
+        assert_eq!(first.series_name.as_deref(), Some("A Series"));
+        assert_eq!(first.series_part, Some(3));
+        assert_eq!(first.last_visited, date(2025, 3, 5));
+        assert_eq!(first.visitations, 4);
+
+        let second = &page.entries[1];
+        assert_eq!(second.visitations, 1);
+        assert_eq!(second.total_chapters, Some(1));
+        assert_eq!(second.summary, None);
+    }
+
+    #[test]
+    fn warns_about_skipped_works() {
+        let page = parse_history_page(&Html::parse_document(PAGE), false);
+        assert_eq!(page.warnings.len(), 2);
+        assert!(page.warnings[0].contains("\"sometime\" of \"Third\""));
+        assert!(page.warnings[1].contains("required tags"));
+        assert!(page.entries[0].summary_html.is_none());
+    }
+
+    #[test]
+    fn empty_page_reaches_before_anything() {
+        let page = parse_history_page(&Html::parse_document("<ol></ol>"), false);
+        assert!(page.reaches_before(date(2000, 1, 1)));
+    }
+}
@@ -1,129 +1,673 @@
-use std::{collections::HashMap, env::var, fs::File, io::Write, path::Path, time::Duration};
-
-use anyhow::{Result, bail};
-use chrono::Datelike;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono::NaiveDate;
 use clap::Parser;
 use polars::prelude::*;
+use reqwest_cookie_store::CookieStoreMutex;
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
 
+use crate::{
+    auth::{PasswordSource, current_user, get_csrf, sign_in, store_password},
+    cache::PageCache,
+    checkpoint::Checkpoint,
+    config::Config,
+    data_dir::DataDir,
+    db::HistoryDb,
+    fetch::{RetryPolicy, fetch_page},
+    history::{parse_history_page, to_dataframe, visit_dates},
+    range::{DateRange, RangeArgs},
+    session::{SessionFile, import_browser_cookies},
+    works::WorksFormat,
+};
+
+mod auth;
+mod cache;
+mod checkpoint;
+mod config;
+mod data_dir;
+mod db;
+mod fetch;
+mod history;
+mod range;
+mod session;
+mod works;
+
 #[derive(Debug, clap::Parser)]
 struct Args {
+    /// The profile from the config file to take defaults from
+    #[arg(short = 'p', long = "profile", global = true)]
+    profile: Option<String>,
+    /// The config file holding profiles, defaults to
+    /// ~/.config/ao3_wrapped/config.toml on Linux
+    #[arg(long = "config", global = true)]
+    config: Option<PathBuf>,
+    /// Where scraped data, page caches and sessions are kept, defaults to the
+    /// platform's data directory (~/.local/share/ao3_wrapped on Linux)
+    #[arg(long = "data-dir", global = true)]
+    data_dir: Option<PathBuf>,
+    /// The AO3 username or login email to use, whose data gets its own
+    /// subdirectory
+    #[arg(short = 'u', long = "user", global = true)]
+    user: Option<String>,
+    /// Where works and stats files are written and read, instead of the
+    /// account's data directory
+    #[arg(short = 'o', long = "output", global = true)]
+    output: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Command {
+    /// Fetch reading history from AO3 and write the works table and stats
     Scrape {
-        /// The year you want to summarize, defaults to current year
-        #[arg(short = 'y', long = "year")]
-        year: Option<i32>,
+        #[command(flatten)]
+        range: RangeArgs,
         /// The page to scrape from
         #[arg(default_value = "readings")]
         scrape_type: String,
-        /// Delay between page loads
-        #[arg(short = 'd', default_value = "6000")]
-        delay_ms: u64,
+        /// Delay between page loads, defaults to the profile's or 6000
+        #[arg(short = 'd')]
+        delay_ms: Option<u64>,
+        /// Continue from the checkpoint left by an interrupted scrape
+        #[arg(short = 'r', long = "resume")]
+        resume: bool,
+        /// Only fetch what's changed since the saved works for this range
+        /// were scraped, and merge it into them
+        #[arg(short = 'i', long = "incremental")]
+        incremental: bool,
+        /// Where raw history pages are saved as they're fetched, defaults to
+        /// the data directory's cache
+        #[arg(long = "cache-dir")]
+        cache_dir: Option<PathBuf>,
+        /// How many times to try fetching a page before giving up
+        #[arg(long = "max-attempts", default_value = "6")]
+        max_attempts: u32,
+        /// Delay before the first retry, doubled after each failure
+        #[arg(long = "retry-delay", default_value = "5000")]
+        retry_delay_ms: u64,
+        /// Longest to back off between retries. A server's Retry-After is
+        /// always waited out in full.
+        #[arg(long = "max-retry-delay", default_value = "300000")]
+        max_retry_delay_ms: u64,
+        /// Where the logged-in session is saved for reuse between runs,
+        /// defaults to one in the data directory
+        #[arg(long = "session-file")]
+        session_file: Option<PathBuf>,
+        /// Log in with cookies exported from a browser (Netscape cookies.txt or JSON) instead
+        #[arg(long = "cookies")]
+        cookies: Option<PathBuf>,
+        /// Where to get the password if logging in: prompt, keyring,
+        /// env:<VARIABLE> or command:<COMMAND>
+        #[arg(long = "password")]
+        password: Option<PasswordSource>,
+        /// Keep each work summary's HTML alongside the plain text
+        #[arg(long = "summary-html")]
+        summary_html: bool,
+        /// File format to save the works table in
+        #[arg(long = "format", value_enum, default_value = "csv")]
+        format: WorksFormat,
+        /// Also add the entries to this SQLite reading log
+        #[arg(long = "db")]
+        db: Option<PathBuf>,
+    },
+    /// Re-parse previously fetched pages from the cache without going online
+    Reparse {
+        /// The user whose cached pages should be parsed, defaults to --user or
+        /// the profile's
+        username: Option<String>,
+        #[command(flatten)]
+        range: RangeArgs,
+        /// The page type that was scraped
+        #[arg(default_value = "readings")]
+        scrape_type: String,
+        /// Where raw history pages were saved, defaults to the data
+        /// directory's cache
+        #[arg(long = "cache-dir")]
+        cache_dir: Option<PathBuf>,
+        /// Keep each work summary's HTML alongside the plain text
+        #[arg(long = "summary-html")]
+        summary_html: bool,
+        /// File format to save the works table in
+        #[arg(long = "format", value_enum, default_value = "csv")]
+        format: WorksFormat,
+        /// Also add the entries to this SQLite reading log
+        #[arg(long = "db")]
+        db: Option<PathBuf>,
     },
+    /// Print stats from a previously saved works table without going online
     StatsOnly {
-        /// The year to load
-        year: i32,
+        /// The year or named range to load, see --range
+        #[arg(conflicts_with_all = ["year", "from", "range", "all"])]
+        period: Option<String>,
+        #[command(flatten)]
+        range: RangeArgs,
+        /// Slice the range out of the full history from `scrape --all`, even
+        /// if the range was also scraped on its own
+        #[arg(long = "from-all")]
+        from_all: bool,
+    },
+    /// Delete the saved login session
+    #[command(alias = "logout")]
+    ForgetSession {
+        /// The saved session to delete, defaults to the one in the data
+        /// directory
+        #[arg(long = "session-file")]
+        session_file: Option<PathBuf>,
     },
+    /// Save an account's password in the OS keyring, for `password = "keyring"`
+    /// profiles and `--password keyring`
+    StorePassword,
+    /// List the profiles in the config file
+    #[command(alias = "list-profiles")]
+    Profiles,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let config = Config::load(args.config.as_deref())?;
+    if let Command::Profiles = args.command {
+        print_profiles(&config);
+        return Ok(());
+    }
+
+    let profile = config.profile(args.profile.as_deref())?;
+    let data = DataDir::new(args.data_dir.or(profile.data_dir))?;
+    let user = args.user.as_deref().or(profile.username.as_deref());
 
-    let (df, stats) = match args.command {
+    let (df, stats, range) = match args.command {
         Command::Scrape {
-            year,
+            range,
             scrape_type,
             delay_ms,
+            resume,
+            incremental,
+            cache_dir,
+            max_attempts,
+            retry_delay_ms,
+            max_retry_delay_ms,
+            session_file,
+            cookies,
+            password,
+            summary_html,
+            format,
+            db,
         } => {
-            let year = year.unwrap_or_else(|| chrono::Local::now().year());
+            let range = range.resolve(None)?;
+            let delay_ms = delay_ms.or(profile.delay_ms).unwrap_or(6000);
+            let password = password.or(profile.password);
+
+            // A login by email can only be checked against the username once
+            // an earlier run has recorded which account it belongs to
+            let expected_user = user.and_then(|login| data.username_for(login));
+            let saved_session = session_file.clone().or_else(|| data.session(user));
+            let saved_cookies = match (&cookies, &saved_session) {
+                (Some(path), _) => Some(import_browser_cookies(path)?),
+                (None, Some(path)) => SessionFile::new(path).load()?,
+                (None, None) => None,
+            };
+            let has_saved_session = saved_cookies.is_some();
+            let jar = Arc::new(CookieStoreMutex::new(saved_cookies.unwrap_or_default()));
 
             let client = reqwest::ClientBuilder::new()
                 .user_agent("AO3Wrapped/1.0.0")
-                .cookie_store(true)
+                .cookie_provider(Arc::clone(&jar))
                 .redirect(reqwest::redirect::Policy::default())
                 .build()
                 .unwrap();
 
-            println!("Getting CSRF token...");
-            let csrf = get_csrf(&client).await?;
-            sleep(Duration::from_secs(2)).await;
-            println!("Logging in...");
-            let username = sign_in(&client, &csrf).await?;
+            let saved_user = if has_saved_session {
+                println!("Checking session cookies...");
+                current_user(&client).await?
+            } else {
+                None
+            };
+
+            let username = match (saved_user, &cookies) {
+                (Some(username), _) => {
+                    if let Some(expected) =
+                        expected_user.filter(|expected| !expected.eq_ignore_ascii_case(&username))
+                    {
+                        bail!("Logged in as {username}, not {expected}");
+                    }
+                    username
+                }
+                (None, Some(path)) => {
+                    bail!(
+                        "The cookies in {} aren't logged in to AO3, try exporting them again",
+                        path.display()
+                    );
+                }
+                (None, None) => {
+                    if has_saved_session {
+                        println!("Saved session has expired");
+                        jar.lock().unwrap().clear();
+                    }
+                    println!("Getting CSRF token...");
+                    let csrf = get_csrf(&client).await?;
+                    sleep(Duration::from_secs(2)).await;
+                    println!("Logging in...");
+                    let username = sign_in(&client, &csrf, user, password.as_ref()).await?;
+                    if let Some(login) = user {
+                        data.remember_login(&username, login)?;
+                    }
+                    username
+                }
+            };
+            let session = SessionFile::new(session_file.unwrap_or_else(|| {
+                data.session(user)
+                    .unwrap_or_else(|| data.user_session(&username))
+            }));
+            session.save(&jar)?;
             println!("Logged in as {username}");
 
-            let mut page = 1;
-            let mut stats = Stats::default();
-            let mut df = DataFrame::empty();
+            let user_dir = data.user(&username);
+            let output = args.output.unwrap_or_else(|| user_dir.clone());
+
+            let mut progress = if resume && Checkpoint::exists(&user_dir, &range) {
+                let checkpoint = Checkpoint::load(&user_dir, &range)?;
+                if checkpoint.username != username || checkpoint.scrape_type != scrape_type {
+                    bail!(
+                        "Checkpoint is for {}'s {}, not {username}'s {scrape_type}",
+                        checkpoint.username,
+                        checkpoint.scrape_type
+                    );
+                }
+                println!("Resuming after page {}...", checkpoint.page);
+                checkpoint
+            } else {
+                if resume {
+                    println!("No checkpoint found for {range}, starting from the beginning");
+                } else if Checkpoint::exists(&user_dir, &range) {
+                    println!(
+                        "Overwriting existing checkpoint for {range} (use --resume to continue it)"
+                    );
+                }
+                Checkpoint {
+                    username,
+                    scrape_type,
+                    range,
+                    page: 0,
+                    entries: Vec::new(),
+                }
+            };
+
+            let saved_works = if incremental {
+                match works::find(&output, &range.label()) {
+                    Some((path, saved_format)) => {
+                        println!("Fetching what's changed since {}...", path.display());
+                        Some(works::read(&path, saved_format)?)
+                    }
+                    None => {
+                        println!("No saved works for {range} yet, scraping all of it");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let known_visits = match &saved_works {
+                Some(saved) => works::visit_keys(saved)?,
+                None => HashSet::new(),
+            };
+
+            let cache = PageCache::new(
+                cache_dir.unwrap_or_else(|| data.cache()),
+                &progress.username,
+                &progress.scrape_type,
+            );
+            let retry_policy = RetryPolicy {
+                max_attempts: max_attempts.max(1),
+                base_delay: Duration::from_millis(retry_delay_ms),
+                max_delay: Duration::from_millis(max_retry_delay_ms),
+            };
+
+            let base_url = format!(
+                "https://archiveofourown.org/users/{}/{}",
+                progress.username, progress.scrape_type
+            );
+            let delay = Duration::from_millis(delay_ms);
+
+            // New visits push the history down while a scrape is paused or
+            // running, so the end of one page can turn up again on the next
+            let mut seen: HashSet<_> = progress
+                .entries
+                .iter()
+                .filter_map(|entry| Some((entry.work_id?, entry.last_visited)))
+                .collect();
+
+            let mut start_page = None;
+            if progress.page == 0 {
+                let (start, html) =
+                    find_start_page(&client, &base_url, range.to, &retry_policy, delay, &cache)
+                        .await?;
+                if start > 1 {
+                    println!("Skipping ahead to page {start}");
+                }
+                progress.page = start - 1;
+                start_page = html;
+                if start_page.is_none() {
+                    sleep(delay).await;
+                }
+            }
+
             loop {
-                println!("Fetching page {page}...");
-                let url = format!(
-                    "https://archiveofourown.org/users/{username}/{scrape_type}?page={page}"
-                );
-
-                let res = loop {
-                    match client.get(&url).send().await?.error_for_status() {
-                        Ok(r) => break r.text().await?,
-                        Err(e) => {
-                            eprintln!("Failed to fetch page {page}: {e}");
-                        }
+                let page = progress.page + 1;
+                let res = match start_page.take() {
+                    Some(res) => res,
+                    None => {
+                        println!("Fetching page {page}...");
+                        let url = format!("{base_url}?page={page}");
+                        let res = fetch_page(&client, &url, &retry_policy).await?;
+                        cache.store(page, &res)?;
+                        res
                     }
                 };
 
                 println!("Processing page...");
                 let doc = Html::parse_document(&res);
 
-                if !parse_hist_page(&doc, &format!("{year}"), &mut stats, &mut df)? {
+                let mut history = parse_history_page(&doc, summary_html);
+                warn_skipped(&history.warnings);
+                history.entries.retain(|entry| {
+                    range.contains(entry.last_visited)
+                        && entry
+                            .work_id
+                            .is_none_or(|id| seen.insert((id, entry.last_visited)))
+                });
+                // History is ordered by last visit, so once an unchanged entry
+                // turns up everything after it is already saved too
+                let caught_up = history.entries.iter().any(|entry| {
+                    entry.work_id.is_some_and(|id| {
+                        known_visits.contains(&(id, entry.last_visited, entry.visitations))
+                    })
+                });
+                progress.entries.append(&mut history.entries);
+                if caught_up {
+                    println!("Caught up with the saved works");
+                    break;
+                }
+                if history.reaches_before(range.from) {
                     break;
                 }
 
-                page += 1;
+                progress.page = page;
+                progress.save(&user_dir)?;
 
                 println!("Waiting {} ms...", delay_ms);
-                sleep(Duration::from_millis(delay_ms)).await;
+                sleep(delay).await;
             }
 
-            std::fs::write(
-                format!("user_{year}.json"),
-                serde_json::to_string_pretty(&stats)?,
-            )?;
-            CsvWriter::new(File::create(format!("works_{year}.csv"))?).finish(&mut df)?;
+            let mut df = to_dataframe(&progress.entries)?;
+            if let Some(saved) = saved_works {
+                df = works::merge(saved, df)?;
+            }
+            let stats = Stats::from_df(&df)?;
+            write_outputs(&output, &range, &stats, &df, format)?;
+            if let Some(db) = &db {
+                let saved = HistoryDb::open(db)?.record(
+                    &progress.username,
+                    &progress.scrape_type,
+                    &range,
+                    &progress.entries,
+                )?;
+                println!("Saved {saved} entries to {}", db.display());
+            }
+            Checkpoint::clear(&user_dir, &range)?;
+            session.save(&jar)?;
 
-            (df, stats)
+            (df, stats, range)
         }
-        Command::StatsOnly { year } => {
-            if !Path::new(&format!("user_{year}.json")).exists() {
-                bail!("User stats file not found");
-            } else if !Path::new(&format!("works_{year}.csv")).exists() {
-                bail!("Works file not found");
+        Command::Reparse {
+            username,
+            range,
+            scrape_type,
+            cache_dir,
+            summary_html,
+            format,
+            db,
+        } => {
+            let username = match (username.as_deref(), user) {
+                (Some(username), _) => username.to_string(),
+                (None, Some(user)) => data
+                    .username_for(user)
+                    .ok_or_else(|| anyhow!("No account has logged in as {user} yet"))?,
+                (None, None) => bail!("Name the account to reparse, or pass --user or --profile"),
+            };
+            let range = range.resolve(None)?;
+            let cache = PageCache::new(
+                cache_dir.unwrap_or_else(|| data.cache()),
+                &username,
+                &scrape_type,
+            );
+            let pages = cache.pages()?;
+            if pages.is_empty() {
+                bail!("No cached pages found in {}", cache.dir().display());
+            }
+
+            let mut entries = Vec::new();
+            // Pages can be left over from scrapes on different days, and
+            // history shifts between those, so a visit may be cached twice
+            let mut seen = HashSet::new();
+            for page in pages {
+                let Some(res) = cache.load(page)? else {
+                    continue;
+                };
+                println!("Processing cached page {page}...");
+                let doc = Html::parse_document(&res);
+
+                let mut history = parse_history_page(&doc, summary_html);
+                warn_skipped(&history.warnings);
+                history.entries.retain(|entry| {
+                    range.contains(entry.last_visited)
+                        && entry
+                            .work_id
+                            .is_none_or(|id| seen.insert((id, entry.last_visited)))
+                });
+                entries.append(&mut history.entries);
+                if history.reaches_before(range.from) {
+                    break;
+                }
             }
 
-            let df = CsvReader::new(File::open(format!("works_{year}.csv"))?).finish()?;
-            let stats =
-                serde_json::from_str(&std::fs::read_to_string(format!("user_{year}.json"))?)?;
+            let df = to_dataframe(&entries)?;
+            let stats = Stats::from_df(&df)?;
+            let output = args.output.unwrap_or_else(|| data.user(&username));
+            write_outputs(&output, &range, &stats, &df, format)?;
+            if let Some(db) = &db {
+                let saved =
+                    HistoryDb::open(db)?.record(&username, &scrape_type, &range, &entries)?;
+                println!("Saved {saved} entries to {}", db.display());
+            }
 
-            (df, stats)
+            (df, stats, range)
         }
+        Command::StatsOnly {
+            period,
+            range,
+            from_all,
+        } => {
+            let range = range.resolve(period.as_deref())?;
+            let label = range.label();
+            let output = match (args.output, user) {
+                (Some(output), _) => output,
+                (None, Some(user)) => data.user(
+                    &data
+                        .username_for(user)
+                        .ok_or_else(|| anyhow!("No account has logged in as {user} yet"))?,
+                ),
+                (None, None) => data.user(&data.only_user()?),
+            };
+            let scraped_alone = works::find(&output, &label);
+            let all = works::find(&output, "all").filter(|_| !range.is_all());
+
+            if let Some((all_path, all_format)) =
+                all.filter(|_| from_all || scraped_alone.is_none())
+            {
+                println!("Slicing {range} out of {}...", all_path.display());
+                let df = works::read(&all_path, all_format)?
+                    .lazy()
+                    .filter(
+                        col("user_last_visited")
+                            .gt_eq(lit(range.from))
+                            .and(col("user_last_visited").lt_eq(lit(range.to))),
+                    )
+                    .collect()?;
+                let stats = Stats::from_df(&df)?;
+
+                (df, stats, range)
+            } else {
+                if from_all {
+                    bail!("Full history not found, run `scrape --all` first");
+                }
+                let Some((path, format)) = scraped_alone else {
+                    bail!("Works file not found");
+                };
+
+                let df = works::read(&path, format)?;
+                let stats = Stats::from_df(&df)?;
+
+                (df, stats, range)
+            }
+        }
+        Command::ForgetSession { session_file } => {
+            let Some(path) = session_file.or_else(|| data.session(user)) else {
+                println!("No saved session for {}", user.unwrap_or_default());
+                return Ok(());
+            };
+            let session = SessionFile::new(path);
+            if session.forget()? {
+                println!("Deleted saved session {}", session.path().display());
+            } else {
+                println!("No saved session at {}", session.path().display());
+            }
+            return Ok(());
+        }
+        Command::StorePassword => {
+            let Some(user) = user else {
+                bail!("Name the account to save a password for with --user or --profile");
+            };
+            store_password(user)?;
+            println!("Saved {user}'s password to the keyring");
+            return Ok(());
+        }
+        Command::Profiles => unreachable!("handled before loading the profile"),
     };
 
-    print_stats(&df.lazy(), &stats)?;
+    // Averaging over every date there is would be meaningless, so a whole
+    // history report covers the span that was actually read instead
+    let range = if range.is_all() {
+        visited_span(&df)?.unwrap_or(range)
+    } else {
+        range
+    };
+
+    print_stats(&df.lazy(), &stats, &range)?;
 
     Ok(())
 }
 
-fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
+/// Writes the works table, plus its stats as JSON for other tools. Reports
+/// never read the JSON back, they always recompute it from the works table.
+fn write_outputs(
+    dir: &Path,
+    range: &DateRange,
+    stats: &Stats,
+    df: &DataFrame,
+    format: WorksFormat,
+) -> Result<()> {
+    let label = range.label();
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(
+        dir.join(format!("user_{label}.json")),
+        serde_json::to_string_pretty(stats)?,
+    )?;
+    works::write(&format.path(dir, &label), format, df)
+}
+
+fn warn_skipped(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+}
+
+fn print_profiles(config: &Config) {
+    let Some(path) = &config.path else {
+        println!(
+            "No config file, create one at {} to add profiles",
+            Config::default_path().map_or("--config".into(), |p| p.display().to_string())
+        );
+        return;
+    };
+    if config.profiles.is_empty() {
+        println!("No profiles in {}", path.display());
+        return;
+    }
+
+    println!("Profiles in {}:", path.display());
+    for (name, profile) in &config.profiles {
+        let default = if config.default_profile.as_ref() == Some(name) {
+            " (default)"
+        } else {
+            ""
+        };
+        println!("{name}{default}");
+        if let Some(username) = &profile.username {
+            println!("    username: {username}");
+        }
+        if let Some(password) = &profile.password {
+            println!("    password: {password}");
+        }
+        if let Some(data_dir) = &profile.data_dir {
+            println!("    data dir: {}", data_dir.display());
+        }
+        if let Some(delay_ms) = profile.delay_ms {
+            println!("    delay: {delay_ms} ms");
+        }
+    }
+}
+
+/// The first and last visit dates in `df`, if it has any.
+fn visited_span(df: &DataFrame) -> Result<Option<DateRange>> {
+    let visited = df.column("user_last_visited")?.date()?;
+    let (Some(first), Some(last)) = (
+        visited.as_date_iter().flatten().min(),
+        visited.as_date_iter().flatten().max(),
+    ) else {
+        return Ok(None);
+    };
+    Ok(Some(DateRange::new(first, last)?))
+}
+
+/// The authors of each work as one string, for sentences about it.
+fn authors() -> Expr {
+    col("authors").list().join(lit(", "), true)
+}
+
+fn print_stats(df: &LazyFrame, stats: &Stats, range: &DateRange) -> Result<()> {
+    let period = range.describe();
+
+    // Any range can be sliced out of the full history, so it may well be empty
+    let height = df.clone().collect()?.height();
+    if height == 0 {
+        println!("No reads {period}.");
+        return Ok(());
+    }
+
     println!(
-        "You've read {} fanfics this year, totaling {} words, or {:.2} words/day. There's about 70000 words in a novel. You could've read {:.2} novels this year, but you read fanfics instead.",
-        df.clone().collect()?.height(),
+        "You've read {} fanfics {period}, totaling {} words, or {:.2} words/day. There's about 70000 words in a novel. You could've read {:.2} novels {period}, but you read fanfics instead.",
+        height,
         stats.user_word_count,
-        stats.user_word_count as f32 / 365.0,
+        stats.user_word_count as f32 / range.days() as f32,
         stats.user_word_count as f32 / 70000.0
     );
 
@@ -131,7 +675,7 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
 
     let most_visited = df
         .clone()
-        .select([col("user_visitations"), col("title"), col("authors")])
+        .select([col("user_visitations"), col("title"), authors()])
         .filter(col("user_visitations").eq(col("user_visitations").max()))
         .collect()?;
 
@@ -177,7 +721,7 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     // Ship type stats
     print_top_and_rest(
         &stats.user_ship_type,
-        "You read {} {key} fics this year.",
+        &format!("You read {{}} {{key}} fics {period}."),
         "You also read",
         |val, key| format!("{} {} fics", val, key),
     );
@@ -185,11 +729,19 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     // Rating stats
     print_top_and_rest(
         &stats.user_rating,
-        "You read {} {key} fics this year.",
+        &format!("You read {{}} {{key}} fics {period}."),
         "You also read",
         |val, key| format!("{} {} fics", val, key),
     );
 
+    // Archive warning stats
+    print_top_and_rest(
+        &stats.user_warnings,
+        &format!("You read {{}} works tagged {{key}} {period}."),
+        "You also read",
+        |val, key| format!("{} works tagged {}", val, key),
+    );
+
     // Status stats
     let mut status_sorted: Vec<_> = stats.user_status.iter().collect();
     status_sorted.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
@@ -197,7 +749,7 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
         let (key0, val0) = status_sorted[0];
         let (key1, val1) = status_sorted[1];
         println!(
-            "You read {} {} and {} {} fics this year.",
+            "You read {} {} and {} {} fics {period}.",
             val0, key0, val1, key1
         );
         println!();
@@ -208,11 +760,11 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     authors_sorted.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
     if let Some((top_key, top_val)) = authors_sorted.first() {
         println!(
-            "You read {} different authors this year.",
+            "You read {} different authors {period}.",
             stats.user_authors.len()
         );
         println!(
-            "Your most read author this year was {}, with {} fics.",
+            "Your most read author {period} was {}, with {} fics.",
             top_key, top_val
         );
         println!("You also read:");
@@ -227,11 +779,11 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     fandoms_sorted.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
     if let Some((top_key, top_val)) = fandoms_sorted.first() {
         println!(
-            "You read fics for {} different fandoms this year.",
+            "You read fics for {} different fandoms {period}.",
             stats.user_fandoms.len()
         );
         println!(
-            "Your most read fandom was {}, with {} fics this year.",
+            "Your most read fandom was {}, with {} fics {period}.",
             top_key, top_val
         );
         println!("You also read:");
@@ -246,11 +798,11 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     ships_sorted.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
     if let Some((top_key, top_val)) = ships_sorted.first() {
         println!(
-            "You read fics with {} different ships this year.",
+            "You read fics with {} different ships {period}.",
             stats.user_ships.len()
         );
         println!(
-            "Are you not tired of reading about {}? You read {} fics of them this year.",
+            "Are you not tired of reading about {}? You read {} fics of them {period}.",
             top_key, top_val
         );
         println!("You also read:");
@@ -265,11 +817,11 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     characters_sorted.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
     if let Some((top_key, top_val)) = characters_sorted.first() {
         println!(
-            "You read about {} different characters this year.",
+            "You read about {} different characters {period}.",
             stats.user_characters.len()
         );
         println!(
-            "What a {} stan. You read {} fics of them this year.",
+            "What a {} stan. You read {} fics of them {period}.",
             top_key, top_val
         );
         println!("You also read:");
@@ -283,14 +835,13 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
     let mut tags_sorted: Vec<_> = stats.user_tags.iter().collect();
     tags_sorted.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
     if let Some((top_key, top_val)) = tags_sorted.first() {
-        let df_height = df.clone().collect()?.height();
         println!(
-            "You read fics with {} different tags this year, averaging {:.2} tags/work.",
+            "You read fics with {} different tags {period}, averaging {:.2} tags/work.",
             stats.user_tags.len(),
-            stats.user_tags.len() as f32 / df_height as f32
+            stats.user_tags.len() as f32 / height as f32
         );
         println!(
-            "You absolutely love {}, but you already knew that. You read {} fics with that tag this year.",
+            "You absolutely love {}, but you already knew that. You read {} fics with that tag {period}.",
             top_key, top_val
         );
         println!("You also read:");
@@ -301,11 +852,64 @@ fn print_stats(df: &LazyFrame, stats: &Stats) -> Result<()> {
 
     println!();
 
+    print_series_stats(df, &period)?;
+
     print_min_max_stats(df)?;
 
     Ok(())
 }
 
+fn print_series_stats(df: &LazyFrame, period: &str) -> Result<()> {
+    const RUNNERS_UP: usize = 9;
+
+    let series = df
+        .clone()
+        .filter(col("series_id").is_not_null())
+        .group_by([col("series_id"), col("series_name")])
+        .agg([
+            len().alias("works"),
+            col("series_part").max().alias("furthest_part"),
+        ])
+        .sort(
+            ["works", "series_name"],
+            SortMultipleOptions::default().with_order_descending_multi([true, false]),
+        )
+        .collect()?;
+
+    if series.height() == 0 {
+        return Ok(());
+    }
+
+    let names = series.column("series_name")?;
+    let works = series.column("works")?;
+    let furthest = series.column("furthest_part")?;
+
+    println!(
+        "You read works from {} different series {period}.",
+        series.height()
+    );
+    println!(
+        "You couldn't get enough of {}, reading {} of its works and getting as far as part {}.",
+        names.get(0)?.str_value(),
+        works.get(0)?,
+        furthest.get(0)?
+    );
+    if series.height() > 1 {
+        println!("You also read:");
+        for i in 1..series.height().min(1 + RUNNERS_UP) {
+            println!(
+                "{} works of {}, up to part {}",
+                works.get(i)?,
+                names.get(i)?.str_value(),
+                furthest.get(i)?
+            );
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
 fn print_min_max_stats(df: &LazyFrame) -> Result<()> {
     fn print_stat(df: &LazyFrame, col_name: &str, label: &str, is_max: bool) -> Result<()> {
         let filtered = df
@@ -315,7 +919,7 @@ fn print_min_max_stats(df: &LazyFrame) -> Result<()> {
             } else {
                 col(col_name).eq(col(col_name).min())
             })
-            .select([col("title"), col("authors"), col(col_name)])
+            .select([col("title"), authors(), col(col_name)])
             .collect()?;
 
         let extremum = if is_max { "Most" } else { "Least" };
@@ -375,75 +979,59 @@ fn print_min_max_stats(df: &LazyFrame) -> Result<()> {
     Ok(())
 }
 
-fn selector(s: impl AsRef<str>) -> Selector {
-    Selector::parse(s.as_ref()).unwrap()
-}
+/// Binary searches the history for the first page with visits on or before
+/// `until`. History is ordered by last visit, newest first, so this avoids
+/// walking every page of newer years one at a time. Also returns the start
+/// page's HTML if it was fetched along the way, so it isn't fetched twice.
+async fn find_start_page(
+    client: &reqwest::Client,
+    base_url: &str,
+    until: NaiveDate,
+    retry_policy: &RetryPolicy,
+    delay: Duration,
+    cache: &PageCache,
+) -> Result<(u32, Option<String>)> {
+    let oldest_visit = |res: &str| visit_dates(&Html::parse_document(res)).into_iter().min();
+
+    let res = fetch_page(client, &format!("{base_url}?page=1"), retry_policy).await?;
+    cache.store(1, &res)?;
+    if oldest_visit(&res).is_none_or(|oldest| oldest <= until) {
+        return Ok((1, Some(res)));
+    }
 
-async fn get_csrf(client: &reqwest::Client) -> Result<String> {
-    let res = client
-        .get("https://archiveofourown.org/users/login")
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
-    let doc = scraper::Html::parse_document(&res);
-    Ok(doc
-        .select(&selector("meta[name=\"csrf-token\"]"))
-        .next()
-        .unwrap()
-        .attr("content")
-        .unwrap()
-        .to_string())
+    let (mut lo, mut hi) = (2, last_page(&Html::parse_document(&res)).unwrap_or(1));
+    let mut hi_page = None;
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        sleep(delay).await;
+        println!("Looking for {until} around page {mid}...");
+        let res = fetch_page(client, &format!("{base_url}?page={mid}"), retry_policy).await?;
+        cache.store(mid, &res)?;
+
+        match oldest_visit(&res) {
+            Some(oldest) if oldest > until => lo = mid + 1,
+            _ => {
+                hi = mid;
+                hi_page = Some(res);
+            }
+        }
+    }
+
+    if lo > hi {
+        return Ok((lo, None));
+    }
+    Ok((hi, hi_page))
 }
 
-async fn sign_in(client: &reqwest::Client, csrf: &str) -> Result<String> {
-    let username = var("AO3_USERNAME").unwrap_or_else(|_| prompt("Enter your username: ", false));
-    let password = var("AO3_PASSWORD").unwrap_or_else(|_| prompt("Enter your password: ", true));
-
-    let params = [
-        ("utf8", "✓"),
-        ("authenticity_token", csrf),
-        ("user[login]", &username),
-        ("user[password]", &password),
-        ("commit", "Log in"),
-    ]
-    .into_iter()
-    .collect::<HashMap<&str, &str>>();
-
-    client
-        .post("https://archiveofourown.org/users/login")
-        .header("Referer", "https://archiveofourown.org/users/login")
-        .header("Origin", "https://archiveofourown.org")
-        .form(&params)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(username)
+/// The highest page number linked from the pagination bar.
+fn last_page(html: &Html) -> Option<u32> {
+    html.select(&selector("ol.pagination li a"))
+        .filter_map(|a| a.text().collect::<String>().trim().parse().ok())
+        .max()
 }
 
-fn prompt(p: &str, secure: bool) -> String {
-    if secure {
-        loop {
-            match rpassword::prompt_password(p) {
-                Ok(pass) => return pass,
-                Err(_) => eprintln!("Invalid password"),
-            }
-        }
-    } else {
-        loop {
-            print!("{p}");
-            let _ = std::io::stdout().flush();
-            let mut line = String::new();
-            std::io::stdin().read_line(&mut line).unwrap();
-            line.trim().to_string();
-            if line.is_empty() {
-                continue;
-            }
-            return line;
-        }
-    }
+fn selector(s: impl AsRef<str>) -> Selector {
+    Selector::parse(s.as_ref()).unwrap()
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -452,6 +1040,8 @@ struct Stats {
     user_fandoms: HashMap<String, u32>,
     user_ship_type: HashMap<String, u32>,
     user_rating: HashMap<String, u32>,
+    #[serde(default)]
+    user_warnings: HashMap<String, u32>,
     user_status: HashMap<String, u32>,
     user_ships: HashMap<String, u32>,
     user_characters: HashMap<String, u32>,
@@ -460,190 +1050,66 @@ struct Stats {
     title_lower_count: u32,
 }
 
-fn parse_hist_page(html: &Html, year: &str, stats: &mut Stats, df: &mut DataFrame) -> Result<bool> {
-    let work_list_sel =
-        Selector::parse("ol.reading.work.index.group li[class*='reading work blurb group']")
-            .unwrap();
-    let user_module_sel = Selector::parse("div.user.module.group h4").unwrap();
-    let header_sel = Selector::parse("div.header.module").unwrap();
-    let title_sel = Selector::parse("h4.heading a").unwrap();
-    let author_sel = Selector::parse("h4.heading a[rel='author']").unwrap();
-    let date_sel = Selector::parse("p").unwrap();
-    let fandom_sel = Selector::parse("h5.fandoms.heading a").unwrap();
-    let req_tag_sel = Selector::parse("ul li a span.text").unwrap();
-    let ship_sel = Selector::parse("ul.tags.commas li.relationships").unwrap();
-    let char_sel = Selector::parse("ul.tags.commas li.characters").unwrap();
-    let tag_sel = Selector::parse("ul.tags.commas li.freeforms").unwrap();
-    let stats_sel = Selector::parse("dl.stats").unwrap();
-    let words_sel = Selector::parse("dd.words").unwrap();
-    let kudos_sel = Selector::parse("dd.kudos a").unwrap();
-    let hits_sel = Selector::parse("dd.hits").unwrap();
-
-    let mut found_in_year = false;
-
-    for work in html.select(&work_list_sel) {
-        // Get last visited date
-        let Some(user_module) = work.select(&user_module_sel).next() else {
-            continue;
-        };
-        let last_visited_text = user_module.text().collect::<String>();
-        let last_visited = last_visited_text
-            .trim()
-            .strip_prefix("Last visited:")
-            .unwrap_or("")
-            .lines()
-            .next()
-            .unwrap_or("")
-            .trim();
-
-        if !last_visited.contains(year) {
-            continue;
-        }
-
-        found_in_year = true;
-
-        // Get title
-        let Some(header) = work.select(&header_sel).next() else {
-            continue;
-        };
-        let Some(title_elem) = header.select(&title_sel).next() else {
-            continue;
-        };
-        let title = title_elem.text().collect::<String>();
-
-        if title == title.to_lowercase() {
-            stats.title_lower_count += 1;
-        }
-
-        // Get authors
-        let mut authors = Vec::new();
-        for author in header.select(&author_sel) {
-            let author_text = author.text().collect::<String>();
-            if author_text != "orphan_account" {
-                authors.push(author_text.clone());
-                *stats.user_authors.entry(author_text).or_insert(0) += 1;
-            }
-        }
-
-        // Get date last updated
-        let updated = header
-            .select(&date_sel)
-            .next()
-            .map(|e| e.text().collect::<String>())
-            .unwrap_or_default();
-
-        // Get fandoms
-        let mut fandoms = Vec::new();
-        for fandom in header.select(&fandom_sel) {
-            let fandom_text = fandom.text().collect::<String>();
-            fandoms.push(fandom_text.clone());
-            *stats.user_fandoms.entry(fandom_text).or_insert(0) += 1;
-        }
-
-        // Get required tags (rating, ship types, status)
-        let req_tags: Vec<String> = header
-            .select(&req_tag_sel)
-            .map(|t| t.text().collect::<String>())
-            .collect();
-
-        if req_tags.len() < 4 {
-            continue;
-        }
-
-        let rating = req_tags[0].clone();
-        *stats.user_rating.entry(rating.clone()).or_insert(0) += 1;
-
-        let mut ship_types = Vec::new();
-        for ship_type in req_tags[2].split(", ") {
-            ship_types.push(ship_type.to_string());
-            *stats
-                .user_ship_type
-                .entry(ship_type.to_string())
-                .or_insert(0) += 1;
-        }
-
-        let work_status = req_tags[3].clone();
-        *stats.user_status.entry(work_status.clone()).or_insert(0) += 1;
-
-        // Get relationships
-        let mut ships = Vec::new();
-        for ship in work.select(&ship_sel) {
-            let ship_text = ship.text().collect::<String>();
-            ships.push(ship_text.clone());
-            *stats.user_ships.entry(ship_text).or_insert(0) += 1;
-        }
+impl Stats {
+    /// Tallies everything the report needs from a works table, so any slice
+    /// or merge of it gets matching stats. List columns count every value.
+    fn from_df(df: &DataFrame) -> Result<Self> {
+        let works = df.clone().lazy();
 
-        // Get characters
-        let mut characters = Vec::new();
-        for character in work.select(&char_sel) {
-            let char_text = character.text().collect::<String>();
-            characters.push(char_text.clone());
-            *stats.user_characters.entry(char_text).or_insert(0) += 1;
-        }
-
-        // Get additional tags
-        let mut additional_tags = Vec::new();
-        for tag in work.select(&tag_sel) {
-            let tag_text = tag.text().collect::<String>();
-            additional_tags.push(tag_text.clone());
-            *stats.user_tags.entry(tag_text).or_insert(0) += 1;
-        }
-
-        // Get stats
-        let Some(stats_elem) = work.select(&stats_sel).next() else {
-            continue;
+        let tally = |column: &str, list: bool| -> Result<HashMap<String, u32>> {
+            let values = if list {
+                col(column).explode()
+            } else {
+                col(column).cast(DataType::String)
+            };
+            let counts = works
+                .clone()
+                .select([values.alias("value")])
+                .filter(col("value").neq(lit("")))
+                .group_by([col("value")])
+                .agg([len().cast(DataType::UInt32).alias("count")])
+                .collect()?;
+
+            let values = counts.column("value")?.str()?;
+            let counts = counts.column("count")?.u32()?;
+            Ok(values
+                .into_iter()
+                .zip(counts)
+                .filter_map(|(value, count)| Some((value?.to_string(), count?)))
+                .collect())
         };
 
-        let word_count = stats_elem
-            .select(&words_sel)
-            .next()
-            .and_then(|e| e.text().collect::<String>().replace(",", "").parse().ok())
-            .unwrap_or(0);
-        stats.user_word_count += word_count;
-
-        let kudos = stats_elem
-            .select(&kudos_sel)
-            .next()
-            .and_then(|e| e.text().collect::<String>().replace(",", "").parse().ok())
-            .unwrap_or(0);
-
-        let hits = stats_elem
-            .select(&hits_sel)
-            .next()
-            .and_then(|e| e.text().collect::<String>().replace(",", "").parse().ok())
-            .unwrap_or(0);
-
-        // Get visitations
-        let visitations_text = last_visited_text
-            .split("Visited ")
-            .nth(1)
-            .and_then(|s| s.split_whitespace().next())
-            .unwrap_or("once");
-
-        let user_visitations = if visitations_text == "once" {
-            1
-        } else {
-            visitations_text.parse().unwrap_or(1)
-        };
+        let totals = works
+            .clone()
+            .select([
+                col("word_count")
+                    .cast(DataType::UInt64)
+                    .sum()
+                    .alias("word_count"),
+                col("title")
+                    .eq(col("title").str().to_lowercase())
+                    .sum()
+                    .cast(DataType::UInt32)
+                    .alias("title_lower_count"),
+            ])
+            .collect()?;
 
-        *df = df.vstack(&df![
-            "title" => [title.as_str()],
-            "authors" => [authors.join(",")],
-            "last_updated" => [updated.as_str()],
-            "fandoms" => [fandoms.join(",")],
-            "characters" => [characters.join(",")],
-            "ship_types" => [ship_types.join(",")],
-            "rating" => [rating.as_str()],
-            "work_stats" => [work_status.as_str()],
-            "ships" => [ships.join(",")],
-            "additional_tags" => [additional_tags.join(",")],
-            "word_count" => [word_count],
-            "kudos" => [kudos],
-            "hits" => [hits],
-            "user_last_visited" => [last_visited],
-            "user_visitations" => [user_visitations]
-        ]?)?;
+        Ok(Self {
+            user_authors: tally("authors", true)?,
+            user_fandoms: tally("fandoms", true)?,
+            user_ship_type: tally("ship_types", true)?,
+            user_rating: tally("rating", false)?,
+            user_warnings: tally("warnings", true)?,
+            user_status: tally("work_stats", false)?,
+            user_ships: tally("ships", true)?,
+            user_characters: tally("characters", true)?,
+            user_tags: tally("additional_tags", true)?,
+            user_word_count: totals.column("word_count")?.u64()?.get(0).unwrap_or(0),
+            title_lower_count: totals
+                .column("title_lower_count")?
+                .u32()?
+                .get(0)
+                .unwrap_or(0),
+        })
     }
-
-    Ok(found_in_year)
 }
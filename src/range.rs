@@ -0,0 +1,297 @@
+use std::fmt::Display;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{Datelike, Local, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Which dates a report covers, shared by every command that filters history.
+#[derive(Debug, clap::Args)]
+pub struct RangeArgs {
+    /// The year you want to summarize, defaults to current year
+    #[arg(short = 'y', long = "year", conflicts_with_all = ["from", "range", "all"])]
+    year: Option<i32>,
+    /// First day to include (YYYY-MM-DD)
+    #[arg(long = "from", conflicts_with_all = ["range", "all"])]
+    from: Option<NaiveDate>,
+    /// Last day to include (YYYY-MM-DD), defaults to today
+    #[arg(long = "to", requires = "from")]
+    to: Option<NaiveDate>,
+    /// A named range, like "2024", "last 12 months", "Q3 2025", "summer 2025", "March 2025" or "all"
+    #[arg(long = "range", conflicts_with = "all")]
+    range: Option<String>,
+    /// Cover the entire reading history, which any range can be sliced out of later
+    #[arg(short = 'a', long = "all")]
+    all: bool,
+}
+
+impl RangeArgs {
+    /// Resolves the arguments into a range, falling back to `shorthand` (for
+    /// commands that also take the range positionally) and then the current
+    /// year.
+    pub fn resolve(&self, shorthand: Option<&str>) -> Result<DateRange> {
+        let today = Local::now().date_naive();
+        if self.all {
+            return Ok(DateRange::all());
+        }
+        if let Some(from) = self.from {
+            return DateRange::new(from, self.to.unwrap_or(today));
+        }
+        if let Some(year) = self.year {
+            return DateRange::year(year);
+        }
+        match self.range.as_deref().or(shorthand) {
+            Some(range) => DateRange::parse(range, today),
+            None => DateRange::year(today.year()),
+        }
+    }
+}
+
+/// An inclusive span of days.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl DateRange {
+    pub fn new(from: NaiveDate, to: NaiveDate) -> Result<Self> {
+        if from > to {
+            bail!("Range starts ({from}) after it ends ({to})");
+        }
+        Ok(Self { from, to })
+    }
+
+    /// Every date there is, for scraping the whole history in one go.
+    pub fn all() -> Self {
+        Self {
+            from: NaiveDate::MIN,
+            to: NaiveDate::MAX,
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        *self == Self::all()
+    }
+
+    pub fn year(year: i32) -> Result<Self> {
+        let from = NaiveDate::from_ymd_opt(year, 1, 1);
+        let to = NaiveDate::from_ymd_opt(year, 12, 31);
+        let (from, to) = from
+            .zip(to)
+            .ok_or_else(|| anyhow!("{year} is out of range"))?;
+        Self::new(from, to)
+    }
+
+    /// `months` whole months starting on the first of `year`/`month`.
+    fn months(year: i32, month: u32, months: u32) -> Result<Self> {
+        let from = NaiveDate::from_ymd_opt(year, month, 1)
+            .ok_or_else(|| anyhow!("{year}-{month} is out of range"))?;
+        let to = from
+            .checked_add_months(Months::new(months))
+            .and_then(|d| d.pred_opt())
+            .ok_or_else(|| anyhow!("{year}-{month} is out of range"))?;
+        Self::new(from, to)
+    }
+
+    /// Parses a named range. Rolling ranges ("last 3 months") end `today`,
+    /// and seasons without a year mean the most recent one to have started.
+    pub fn parse(s: &str, today: NaiveDate) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        let words: Vec<_> = s.split_whitespace().collect();
+        let unknown = || anyhow!("Unrecognized range {s:?}");
+
+        if let Ok(year) = s.parse() {
+            return Self::year(year);
+        }
+        if s == "all" {
+            return Ok(Self::all());
+        }
+        if s == "this year" {
+            return Self::year(today.year());
+        }
+        if s == "last year" {
+            return Self::year(today.year() - 1);
+        }
+
+        // "last 12 months", "last 30 days", "last 2 weeks", "last 1 year"
+        if let ["last" | "past", n, unit] = words[..] {
+            let n: u32 = n.parse().map_err(|_| unknown())?;
+            let from = match unit.trim_end_matches('s') {
+                "day" => today.checked_sub_days(chrono::Days::new(n.into())),
+                "week" => today.checked_sub_days(chrono::Days::new(u64::from(n) * 7)),
+                "month" => today.checked_sub_months(Months::new(n)),
+                "year" => today.checked_sub_months(Months::new(n * 12)),
+                _ => return Err(unknown()),
+            }
+            .and_then(|d| d.succ_opt())
+            .ok_or_else(unknown)?;
+            return Self::new(from, today);
+        }
+
+        // "q3 2025" or "2025 q3"
+        if let [a, b] = words[..] {
+            let (quarter, year) = if a.starts_with('q') { (a, b) } else { (b, a) };
+            if let (Some(Ok(quarter)), Ok(year)) = (
+                quarter.strip_prefix('q').map(str::parse::<u32>),
+                year.parse::<i32>(),
+            ) {
+                if !(1..=4).contains(&quarter) {
+                    bail!("There's no Q{quarter}");
+                }
+                return Self::months(year, (quarter - 1) * 3 + 1, 3);
+            }
+        }
+
+        // "summer", "winter 2024"
+        let season_start = match words.first().copied() {
+            Some("spring") => Some(3),
+            Some("summer") => Some(6),
+            Some("fall" | "autumn") => Some(9),
+            Some("winter") => Some(12),
+            _ => None,
+        };
+        if let Some(month) = season_start {
+            let year = match words[1..] {
+                [] => {
+                    let this_year = Self::months(today.year(), month, 3)?;
+                    if this_year.from > today {
+                        today.year() - 1
+                    } else {
+                        today.year()
+                    }
+                }
+                [year] => year.parse().map_err(|_| unknown())?,
+                _ => return Err(unknown()),
+            };
+            return Self::months(year, month, 3);
+        }
+
+        // "march 2025"
+        if let Ok(first) = NaiveDate::parse_from_str(&format!("1 {s}"), "%d %B %Y") {
+            return Self::months(first.year(), first.month(), 1);
+        }
+
+        Err(unknown())
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+
+    /// Whether the range is exactly one calendar year.
+    fn as_year(&self) -> Option<i32> {
+        (Self::year(self.from.year()).ok() == Some(*self)).then_some(self.from.year())
+    }
+
+    /// Used to name output files, "2024" for a whole year, "all" for the whole
+    /// history and "2025-07-01_2025-09-30" otherwise.
+    pub fn label(&self) -> String {
+        if self.is_all() {
+            return "all".to_string();
+        }
+        match self.as_year() {
+            Some(year) => year.to_string(),
+            None => format!("{}_{}", self.from, self.to),
+        }
+    }
+
+    /// How many days the range has actually covered so far, so a range that
+    /// isn't over yet isn't averaged over days that haven't happened.
+    pub fn days(&self) -> i64 {
+        let today = Local::now().date_naive();
+        let to = if self.from <= today {
+            self.to.min(today)
+        } else {
+            self.to
+        };
+        (to - self.from).num_days() + 1
+    }
+
+    /// Describes the range to finish a sentence, like "this year" or "in 2023".
+    pub fn describe(&self) -> String {
+        if self.is_all() {
+            return "across your whole history".to_string();
+        }
+        match self.as_year() {
+            Some(year) if year == Local::now().year() => "this year".to_string(),
+            Some(year) => format!("in {year}"),
+            None => format!("between {} and {}", self.from, self.to),
+        }
+    }
+}
+
+impl Display for DateRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_all() {
+            return write!(f, "the whole history");
+        }
+        match self.as_year() {
+            Some(year) => write!(f, "{year}"),
+            None => write!(f, "{} to {}", self.from, self.to),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn range(from: NaiveDate, to: NaiveDate) -> DateRange {
+        DateRange { from, to }
+    }
+
+    #[test]
+    fn parses_quarters() {
+        let today = date(2025, 10, 16);
+        let q3 = range(date(2025, 7, 1), date(2025, 9, 30));
+        assert_eq!(DateRange::parse("q3 2025", today).unwrap(), q3);
+        assert_eq!(DateRange::parse("2025 Q3", today).unwrap(), q3);
+        assert!(DateRange::parse("q5 2025", today).is_err());
+    }
+
+    #[test]
+    fn winter_without_a_year_is_the_last_one_to_start() {
+        let january = date(2025, 1, 15);
+        assert_eq!(
+            DateRange::parse("winter", january).unwrap(),
+            range(date(2024, 12, 1), date(2025, 2, 28))
+        );
+        assert_eq!(
+            DateRange::parse("winter 2023", january).unwrap(),
+            range(date(2023, 12, 1), date(2024, 2, 29))
+        );
+    }
+
+    #[test]
+    fn rolling_ranges_end_today() {
+        let today = date(2025, 3, 31);
+        assert_eq!(
+            DateRange::parse("last 12 months", today).unwrap(),
+            range(date(2024, 4, 1), today)
+        );
+        assert_eq!(
+            DateRange::parse("past 7 days", today).unwrap(),
+            range(date(2025, 3, 25), today)
+        );
+    }
+
+    #[test]
+    fn parses_years_and_months() {
+        let today = date(2025, 3, 31);
+        assert_eq!(DateRange::parse("2024", today).unwrap().label(), "2024");
+        assert_eq!(
+            DateRange::parse("last year", today).unwrap(),
+            DateRange::year(2024).unwrap()
+        );
+        assert_eq!(
+            DateRange::parse("February 2024", today).unwrap(),
+            range(date(2024, 2, 1), date(2024, 2, 29))
+        );
+        assert!(DateRange::parse("All", today).unwrap().is_all());
+        assert!(DateRange::parse("someday", today).is_err());
+    }
+}
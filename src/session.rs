@@ -0,0 +1,270 @@
+use std::{
+    fs::OpenOptions,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow, bail};
+use cookie_store::CookieStore;
+use reqwest_cookie_store::CookieStoreMutex;
+
+/// A cookie jar saved after logging in, so later runs can skip the login.
+#[derive(Debug)]
+pub struct SessionFile {
+    path: PathBuf,
+}
+
+impl SessionFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Loads the saved cookies, dropping any that have expired since. Returns
+    /// `None` if there's no saved session.
+    pub fn load(&self) -> Result<Option<CookieStore>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(std::fs::File::open(&self.path)?);
+        let store = cookie_store::serde::json::load(reader)
+            .map_err(|e| anyhow!("Failed to read session {}: {e}", self.path.display()))?;
+        Ok(Some(store))
+    }
+
+    /// Saves every cookie in `jar`, including the browser-session ones, since
+    /// AO3's session cookie doesn't carry an expiry of its own. The file is
+    /// only readable by the current user as it's as good as a password.
+    pub fn save(&self, jar: &CookieStoreMutex) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        // `mode` only applies to newly created files, so tighten old ones too
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+        let store = jar
+            .lock()
+            .map_err(|_| anyhow!("Cookie jar lock poisoned"))?;
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(&store, &mut file)
+            .map_err(|e| anyhow!("Failed to save session {}: {e}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Deletes the saved session. Returns whether there was one to delete.
+    pub fn forget(&self) -> Result<bool> {
+        if !self.path.exists() {
+            return Ok(false);
+        }
+        std::fs::remove_file(&self.path)?;
+        Ok(true)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Builds a cookie store from a browser cookie export, either a Netscape
+/// `cookies.txt` or a JSON export (a list of cookies, or an object with a
+/// `cookies` list). Only AO3's cookies are kept.
+pub fn import_browser_cookies(path: &Path) -> Result<CookieStore> {
+    let contents = std::fs::read_to_string(path)?;
+    let trimmed = contents.trim_start();
+    let cookies = if trimmed.starts_with('[') || trimmed.starts_with('{') {
+        parse_json_cookies(trimmed)?
+    } else {
+        parse_netscape_cookies(&contents)
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let mut store = CookieStore::default();
+    for cookie in cookies {
+        if !cookie
+            .domain
+            .trim_start_matches('.')
+            .ends_with("archiveofourown.org")
+        {
+            continue;
+        }
+        if cookie.expires.is_some_and(|at| at <= now) {
+            continue;
+        }
+
+        let host = cookie.domain.trim_start_matches('.');
+        let url = reqwest::Url::parse(&format!("https://{host}{}", cookie.path))?;
+        let mut set_cookie = format!("{}={}; Path={}", cookie.name, cookie.value, cookie.path);
+        // Host-only cookies have no leading dot and mustn't get a Domain attribute
+        if cookie.domain.starts_with('.') {
+            set_cookie += &format!("; Domain={host}");
+        }
+        if cookie.secure {
+            set_cookie += "; Secure";
+        }
+        if cookie.http_only {
+            set_cookie += "; HttpOnly";
+        }
+        if let Some(at) = cookie.expires {
+            set_cookie += &format!("; Max-Age={}", at - now);
+        }
+
+        store
+            .parse(&set_cookie, &url)
+            .map_err(|e| anyhow!("Invalid cookie {} in {}: {e}", cookie.name, path.display()))?;
+    }
+
+    if store.iter_unexpired().next().is_none() {
+        bail!("No unexpired AO3 cookies found in {}", path.display());
+    }
+
+    Ok(store)
+}
+
+#[derive(Debug)]
+struct ExportedCookie {
+    domain: String,
+    path: String,
+    name: String,
+    value: String,
+    secure: bool,
+    http_only: bool,
+    /// Unix timestamp, `None` for session cookies
+    expires: Option<i64>,
+}
+
+/// Parses the tab-separated `cookies.txt` format used by curl and most
+/// browser export extensions.
+fn parse_netscape_cookies(contents: &str) -> Vec<ExportedCookie> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            // curl marks HttpOnly cookies by prefixing what's otherwise a comment
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(rest) => (rest, true),
+                None if line.starts_with('#') => return None,
+                None => (line, false),
+            };
+
+            let fields: Vec<_> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+            let [domain, _, path, secure, expires, name, value] = fields[..] else {
+                return None;
+            };
+
+            Some(ExportedCookie {
+                domain: domain.to_string(),
+                path: path.to_string(),
+                name: name.to_string(),
+                value: value.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                http_only,
+                expires: expires.parse().ok().filter(|&at| at > 0),
+            })
+        })
+        .collect()
+}
+
+/// Parses JSON exports from extensions like Cookie-Editor, which store the
+/// expiry as `expirationDate`, as well as Playwright-style `expires`.
+fn parse_json_cookies(contents: &str) -> Result<Vec<ExportedCookie>> {
+    let json: serde_json::Value = serde_json::from_str(contents)?;
+    let list = match &json {
+        serde_json::Value::Array(list) => list,
+        serde_json::Value::Object(obj) => match obj.get("cookies") {
+            Some(serde_json::Value::Array(list)) => list,
+            _ => bail!("JSON cookie export has no \"cookies\" list"),
+        },
+        _ => bail!("Unrecognized JSON cookie export"),
+    };
+
+    Ok(list
+        .iter()
+        .filter_map(|cookie| {
+            let str_field = |key| cookie.get(key).and_then(|v| v.as_str());
+            let bool_field = |key| cookie.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+
+            let expires = if bool_field("session") {
+                None
+            } else {
+                cookie
+                    .get("expirationDate")
+                    .or_else(|| cookie.get("expires"))
+                    .and_then(|v| v.as_f64())
+                    .filter(|&at| at > 0.0)
+                    .map(|at| at as i64)
+            };
+
+            Some(ExportedCookie {
+                domain: str_field("domain")?.to_string(),
+                path: str_field("path").unwrap_or("/").to_string(),
+                name: str_field("name")?.to_string(),
+                value: str_field("value")?.to_string(),
+                secure: bool_field("secure"),
+                http_only: bool_field("httpOnly"),
+                expires,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_netscape_cookies() {
+        let contents = "# Netscape HTTP Cookie File\n\
+            #HttpOnly_.archiveofourown.org\tTRUE\t/\tTRUE\t0\t_otwarchive_session\tabc\n\
+            archiveofourown.org\tFALSE\t/works\tFALSE\t1900000000\tview_adult\ttrue\r\n\
+            \n\
+            not\tenough\tfields\n";
+        let cookies = parse_netscape_cookies(contents);
+        assert_eq!(cookies.len(), 2);
+
+        let session = &cookies[0];
+        assert_eq!(session.domain, ".archiveofourown.org");
+        assert_eq!(session.name, "_otwarchive_session");
+        assert_eq!(session.value, "abc");
+        assert!(session.secure && session.http_only);
+        assert_eq!(session.expires, None);
+
+        let adult = &cookies[1];
+        assert_eq!(adult.path, "/works");
+        assert_eq!(adult.value, "true");
+        assert!(!adult.secure && !adult.http_only);
+        assert_eq!(adult.expires, Some(1900000000));
+    }
+
+    #[test]
+    fn parses_json_cookies() {
+        let list = r#"[
+            {"domain": ".archiveofourown.org", "name": "a", "value": "1",
+             "secure": true, "httpOnly": true, "expirationDate": 1900000000.5},
+            {"domain": "archiveofourown.org", "path": "/x", "name": "b", "value": "2",
+             "session": true, "expirationDate": 1900000000},
+            {"domain": "archiveofourown.org", "name": "missing value"}
+        ]"#;
+        let cookies = parse_json_cookies(list).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].path, "/");
+        assert!(cookies[0].secure && cookies[0].http_only);
+        assert_eq!(cookies[0].expires, Some(1900000000));
+        assert_eq!(cookies[1].path, "/x");
+        assert_eq!(cookies[1].expires, None);
+
+        let playwright = r#"{"cookies": [
+            {"domain": "archiveofourown.org", "name": "c", "value": "3", "expires": -1}
+        ]}"#;
+        let cookies = parse_json_cookies(playwright).unwrap();
+        assert_eq!(cookies[0].expires, None);
+
+        assert!(parse_json_cookies(r#"{"other": []}"#).is_err());
+        assert!(parse_json_cookies("3").is_err());
+    }
+}
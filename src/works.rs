@@ -0,0 +1,434 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use polars::prelude::*;
+
+/// Columns holding several values per work, like tags.
+pub const LIST_COLUMNS: [&str; 8] = [
+    "authors",
+    "author_urls",
+    "fandoms",
+    "characters",
+    "ship_types",
+    "warnings",
+    "ships",
+    "additional_tags",
+];
+
+/// Columns holding dates, which JSON has no type for.
+const DATE_COLUMNS: [&str; 2] = ["published", "user_last_visited"];
+
+/// File formats the works table can be saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WorksFormat {
+    Csv,
+    Parquet,
+    Json,
+    Ndjson,
+}
+
+impl WorksFormat {
+    const ALL: [Self; 4] = [Self::Csv, Self::Parquet, Self::Json, Self::Ndjson];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    /// Where the works table for `label` is saved in `dir` in this format.
+    pub fn path(self, dir: &Path, label: &str) -> PathBuf {
+        dir.join(format!("works_{label}.{}", self.extension()))
+    }
+}
+
+/// Finds the works table saved in `dir` for `label`. If it was saved in
+/// several formats, the most recently written one wins.
+pub fn find(dir: &Path, label: &str) -> Option<(PathBuf, WorksFormat)> {
+    WorksFormat::ALL
+        .into_iter()
+        .map(|format| (format.path(dir, label), format))
+        .filter_map(|(path, format)| {
+            let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, path, format))
+        })
+        .max_by_key(|(modified, ..)| *modified)
+        .map(|(_, path, format)| (path, format))
+}
+
+pub fn write(path: &Path, format: WorksFormat, df: &DataFrame) -> Result<()> {
+    let file = File::create(path)?;
+    match format {
+        WorksFormat::Csv => {
+            write_csv(file, df)?;
+            write_long_csv(&long_path(path), df)?;
+        }
+        WorksFormat::Parquet => {
+            ParquetWriter::new(file).finish(&mut df.clone())?;
+        }
+        WorksFormat::Json => JsonWriter::new(file)
+            .with_json_format(JsonFormat::Json)
+            .finish(&mut df.clone())?,
+        WorksFormat::Ndjson => JsonWriter::new(file)
+            .with_json_format(JsonFormat::JsonLines)
+            .finish(&mut df.clone())?,
+    }
+    Ok(())
+}
+
+pub fn read(path: &Path, format: WorksFormat) -> Result<DataFrame> {
+    // JSON can't tell a list that's empty in every row apart from a list of
+    // nulls, so tell the reader what the list columns hold
+    let lists = Schema::from_iter(
+        LIST_COLUMNS
+            .map(|name| Field::new(name.into(), DataType::List(Box::new(DataType::String)))),
+    );
+    let df = match format {
+        WorksFormat::Csv => return read_csv(path),
+        WorksFormat::Parquet => ParquetReader::new(File::open(path)?).finish()?,
+        WorksFormat::Json => JsonReader::new(File::open(path)?)
+            .with_json_format(JsonFormat::Json)
+            .with_schema_overwrite(&lists)
+            .finish()?,
+        WorksFormat::Ndjson => JsonReader::new(File::open(path)?)
+            .with_json_format(JsonFormat::JsonLines)
+            .with_schema_overwrite(&lists)
+            .infer_schema_len(None)
+            .finish()?,
+    };
+    restore_dates(df)
+}
+
+/// Every (work ID, last visited, visit count) in a works table, which is
+/// enough to tell whether a history entry has changed since.
+pub fn visit_keys(df: &DataFrame) -> Result<HashSet<(u64, NaiveDate, u32)>> {
+    let ids = df.column("work_id")?.cast(&DataType::UInt64)?;
+    let visited = df.column("user_last_visited")?.date()?.clone();
+    let visits = df.column("user_visitations")?.cast(&DataType::UInt32)?;
+    Ok(ids
+        .u64()?
+        .into_iter()
+        .zip(visited.as_date_iter())
+        .zip(visits.u32()?)
+        .filter_map(|((id, visited), visits)| Some((id?, visited?, visits?)))
+        .collect())
+}
+
+/// Merges newly scraped rows into a saved works table. A work that was
+/// scraped again replaces its old row, and the result is ordered by last
+/// visit like AO3's history. Columns the saved table predates are left
+/// empty for its rows.
+pub fn merge(saved: DataFrame, new: DataFrame) -> Result<DataFrame> {
+    let new_ids: HashSet<_> = new
+        .column("work_id")?
+        .cast(&DataType::UInt64)?
+        .u64()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let saved_ids = saved.column("work_id")?.cast(&DataType::UInt64)?;
+    let kept: BooleanChunked = saved_ids
+        .u64()?
+        .into_iter()
+        .map(|id| id.is_none_or(|id| !new_ids.contains(&id)))
+        .collect();
+    let saved = saved.filter(&kept)?;
+
+    // Line the saved table up with the new one's columns and dtypes, since
+    // it may have been read back from a format that lost them
+    let mut columns = Vec::with_capacity(new.width());
+    for column in new.get_columns() {
+        columns.push(match saved.column(column.name()) {
+            Ok(old) => old.cast(column.dtype())?,
+            Err(_) => Column::full_null(column.name().clone(), saved.height(), column.dtype()),
+        });
+    }
+    let saved = DataFrame::new(columns)?;
+
+    Ok(new.vstack(&saved)?.sort(
+        ["user_last_visited"],
+        SortMultipleOptions::default()
+            .with_order_descending(true)
+            .with_maintain_order(true),
+    )?)
+}
+
+/// JSON stores dates as "YYYY-MM-DD" strings, so turn them back into dates.
+fn restore_dates(mut df: DataFrame) -> Result<DataFrame> {
+    for name in DATE_COLUMNS {
+        let dates = df.column(name)?.cast(&DataType::Date)?;
+        df.with_column(dates)?;
+    }
+    Ok(df)
+}
+
+/// CSV has no list type, so list columns are written as JSON arrays, which
+/// keeps tags with commas in them intact and can be exploded by anything
+/// that reads JSON.
+fn write_csv(file: File, df: &DataFrame) -> Result<()> {
+    let mut encoded = df.clone();
+    for name in LIST_COLUMNS {
+        encoded.with_column(encode_list(df.column(name)?)?)?;
+    }
+    CsvWriter::new(file).finish(&mut encoded)?;
+    Ok(())
+}
+
+/// Where the long form of the works CSV at `path` goes, next to it.
+fn long_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}_long.csv"))
+}
+
+/// Writes every list value on a row of its own, as `work_id,column,value`,
+/// so tags can be counted or joined back to the works without parsing
+/// anything.
+fn write_long_csv(path: &Path, df: &DataFrame) -> Result<()> {
+    let ids = df.column("work_id")?.cast(&DataType::UInt64)?;
+    let (mut work_ids, mut columns, mut values) = (Vec::new(), Vec::new(), Vec::new());
+    for name in LIST_COLUMNS {
+        for (id, items) in ids.u64()?.into_iter().zip(df.column(name)?.list()?) {
+            let Some(items) = items else {
+                continue;
+            };
+            for value in items.str()?.into_iter().flatten() {
+                work_ids.push(id);
+                columns.push(name);
+                values.push(value.to_string());
+            }
+        }
+    }
+
+    let mut long = DataFrame::new(vec![
+        Column::new("work_id".into(), work_ids),
+        Column::new("column".into(), columns),
+        Column::new("value".into(), values),
+    ])?;
+    CsvWriter::new(File::create(path)?).finish(&mut long)?;
+    Ok(())
+}
+
+/// Reads a works CSV back, restoring dates and list columns.
+fn read_csv(path: &Path) -> Result<DataFrame> {
+    let mut df = CsvReadOptions::default()
+        .with_parse_options(CsvParseOptions::default().with_try_parse_dates(true))
+        .try_into_reader_with_file_path(Some(path.to_path_buf()))?
+        .finish()?;
+    for name in LIST_COLUMNS {
+        let decoded = decode_list(df.column(name)?)?;
+        df.with_column(decoded)?;
+    }
+    Ok(df)
+}
+
+fn encode_list(column: &Column) -> Result<Column> {
+    let mut values = Vec::with_capacity(column.len());
+    for items in column.list()? {
+        values.push(match items {
+            Some(items) => Some(serde_json::to_string(
+                &items.str()?.into_iter().flatten().collect::<Vec<_>>(),
+            )?),
+            None => None,
+        });
+    }
+    Ok(Column::new(column.name().clone(), values))
+}
+
+/// Parses a list column written by `encode_list`.
+fn decode_list(column: &Column) -> Result<Column> {
+    let values = column.cast(&DataType::String)?;
+    let mut builder =
+        ListStringChunkedBuilder::new(column.name().clone(), column.len(), column.len() * 4);
+    for value in values.str()? {
+        match value {
+            Some(json) => {
+                let items: Vec<String> = serde_json::from_str(json).map_err(|_| {
+                    anyhow!(
+                        "{} holds {json:?} rather than a JSON list, scrape again to replace \
+                         works files from older versions",
+                        column.name()
+                    )
+                })?;
+                builder.append_values_iter(items.iter().map(String::as_str));
+            }
+            None => builder.append_values_iter(std::iter::empty()),
+        }
+    }
+    Ok(builder.finish().into_column())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Stats,
+        history::{HistoryEntry, to_dataframe},
+    };
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn entry(work_id: Option<u64>, last_visited: NaiveDate, visitations: u32) -> HistoryEntry {
+        HistoryEntry {
+            work_id,
+            work_url: work_id.map(|id| format!("https://archiveofourown.org/works/{id}")),
+            title: format!("Work {work_id:?}"),
+            authors: vec!["a".into(), "b, c".into()],
+            author_urls: vec![],
+            last_updated: "01 Jan 2025".into(),
+            fandoms: vec!["Fandom".into()],
+            characters: vec![],
+            ship_types: vec!["Gen".into()],
+            rating: "General Audiences".into(),
+            warnings: vec!["No Archive Warnings Apply".into()],
+            status: "Complete Work".into(),
+            ships: vec![],
+            additional_tags: vec!["Hurt/Comfort, but mostly hurt".into()],
+            word_count: 1000,
+            kudos: 10,
+            hits: 100,
+            language: Some("English".into()),
+            current_chapters: Some(1),
+            total_chapters: None,
+            comments: 0,
+            bookmarks: 0,
+            published: Some(date(2024, 6, 1)),
+            summary: None,
+            summary_html: None,
+            series_id: None,
+            series_name: None,
+            series_part: None,
+            last_visited,
+            visitations,
+        }
+    }
+
+    /// A path in the temp directory no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ao3_wrapped_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        // `ships` and `characters` are empty in every row, which JSON can't
+        // type on its own
+        let entries = [
+            entry(Some(1), date(2025, 3, 1), 2),
+            entry(Some(2), date(2025, 2, 1), 1),
+        ];
+        let df = to_dataframe(&entries).unwrap();
+
+        for format in WorksFormat::ALL {
+            let path = temp_path(&format!("works.{}", format.extension()));
+            write(&path, format, &df).unwrap();
+            let read_back = read(&path, format).unwrap();
+            let _ = std::fs::remove_file(&path);
+            let _ = std::fs::remove_file(long_path(&path));
+
+            assert_eq!(read_back.height(), 2, "{format:?}");
+            for name in LIST_COLUMNS {
+                assert_eq!(
+                    read_back.column(name).unwrap().dtype(),
+                    &DataType::List(Box::new(DataType::String)),
+                    "{name} in {format:?}"
+                );
+            }
+            assert_eq!(
+                read_back.column("user_last_visited").unwrap().dtype(),
+                &DataType::Date,
+                "{format:?}"
+            );
+
+            let stats = Stats::from_df(&read_back).unwrap();
+            assert_eq!(stats.user_word_count, 2000, "{format:?}");
+            assert_eq!(stats.user_authors.get("b, c"), Some(&2), "{format:?}");
+            assert!(stats.user_ships.is_empty(), "{format:?}");
+        }
+    }
+
+    fn ids(df: &DataFrame) -> Vec<Option<u64>> {
+        let ids = df
+            .column("work_id")
+            .unwrap()
+            .cast(&DataType::UInt64)
+            .unwrap();
+        ids.u64().unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn visit_keys_skip_works_without_an_id() {
+        let df = to_dataframe(&[
+            entry(Some(1), date(2025, 3, 1), 2),
+            entry(None, date(2025, 2, 1), 1),
+        ])
+        .unwrap();
+        assert_eq!(
+            visit_keys(&df).unwrap(),
+            HashSet::from([(1, date(2025, 3, 1), 2)])
+        );
+    }
+
+    #[test]
+    fn revisited_works_replace_their_saved_row() {
+        let saved = to_dataframe(&[
+            entry(Some(1), date(2025, 2, 1), 1),
+            entry(None, date(2025, 1, 15), 1),
+            entry(Some(2), date(2025, 1, 1), 1),
+        ])
+        .unwrap();
+        let new = to_dataframe(&[entry(Some(2), date(2025, 3, 1), 2)]).unwrap();
+
+        let merged = merge(saved, new).unwrap();
+        assert_eq!(ids(&merged), [Some(2), Some(1), None]);
+        let visits = merged.column("user_visitations").unwrap();
+        assert_eq!(visits.u32().unwrap().get(0), Some(2));
+    }
+
+    #[test]
+    fn columns_missing_from_the_saved_table_are_left_empty() {
+        let saved = to_dataframe(&[entry(Some(1), date(2025, 1, 1), 1)])
+            .unwrap()
+            .drop_many(["series_id", "summary_html"]);
+        let new = to_dataframe(&[entry(Some(2), date(2025, 3, 1), 1)]).unwrap();
+
+        let merged = merge(saved, new.clone()).unwrap();
+        assert_eq!(merged.schema(), new.schema());
+        assert_eq!(merged.column("series_id").unwrap().null_count(), 2);
+        assert_eq!(merged.column("title").unwrap().null_count(), 0);
+    }
+
+    #[test]
+    fn saved_csv_lines_up_with_the_new_rows() {
+        let path = temp_path("merge.csv");
+        let saved = to_dataframe(&[entry(Some(1), date(2025, 1, 1), 1)]).unwrap();
+        write(&path, WorksFormat::Csv, &saved).unwrap();
+        let saved = read(&path, WorksFormat::Csv).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(long_path(&path));
+        assert_eq!(saved.column("work_id").unwrap().dtype(), &DataType::Int64);
+
+        let new = to_dataframe(&[entry(Some(2), date(2025, 3, 1), 1)]).unwrap();
+        let merged = merge(saved, new.clone()).unwrap();
+        assert_eq!(merged.schema(), new.schema());
+        assert_eq!(ids(&merged), [Some(2), Some(1)]);
+        assert_eq!(
+            merged
+                .column("authors")
+                .unwrap()
+                .get(1)
+                .unwrap()
+                .str_value(),
+            new.column("authors").unwrap().get(0).unwrap().str_value()
+        );
+    }
+}